@@ -0,0 +1,43 @@
+//! Events per second written by `UInputDevice`, unbatched and batched.
+//!
+//! A regular file stands in for `/dev/uinput`, run with `cargo bench`.
+extern crate keybd_event;
+
+#[cfg(target_os = "linux")]
+fn main() {
+    use std::fs::{self, File};
+    use std::time::Instant;
+
+    use keybd_event::linux::{UInputDevice, EV_KEY};
+
+    const CYCLES: usize = 100_000;
+
+    let path = std::env::temp_dir().join(format!("keybd_event-bench-{}", std::process::id()));
+    for &batched in [false, true].iter() {
+        let mut device = UInputDevice::from_file(File::create(&path).unwrap());
+        let start = Instant::now();
+        for _ in 0..CYCLES {
+            device.write_event(EV_KEY, 30, 1).unwrap();
+            device.synchronize().unwrap();
+            if !batched {
+                device.flush().unwrap();
+            }
+            device.write_event(EV_KEY, 30, 0).unwrap();
+            device.synchronize().unwrap();
+            device.flush().unwrap();
+        }
+        let elapsed = start.elapsed();
+        let events = (CYCLES * 4) as f64;
+        println!(
+            "{}: {} events in {:?}, {:.0} events/s",
+            if batched { "batched" } else { "unbatched" },
+            events,
+            elapsed,
+            events / elapsed.as_secs_f64()
+        );
+    }
+    fs::remove_file(&path).unwrap();
+}
+
+#[cfg(not(target_os = "linux"))]
+fn main() {}
@@ -0,0 +1,261 @@
+//! An async `KeyBondingInstance` for the tokio runtime, with the feature `tokio`.
+//!
+//! The keys are sent from the thread of a `KeyboardWorker`, so no device write blocks the
+//! runtime. As any future, nothing is sent before the future is polled, the operations are queued
+//! in the order of their first poll, and the futures must be polled within a tokio runtime.
+//!
+//! `AsyncKeyBondingInstance::wait` uses the timers of the runtime, but the waits inside an
+//! operation, the hold of `hold_for` and the delays between keys, are sleeps of the worker thread:
+//! they do not block the runtime, the next operations wait for them.
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::thread;
+use std::time::Duration;
+
+use tokio::sync::mpsc::{self, error::SendError, OwnedPermit, Sender};
+use tokio::sync::oneshot;
+use tokio::task::{self, JoinHandle};
+use tokio::time::{self, Sleep};
+
+use progress::{CancelToken, Completion};
+use worker::{stopped_completion, Command, Job, KeyboardWorker};
+use {KeyBonding, KeyboardKey};
+
+/// Counterpart of `KeyBondingInstance` whose operations are futures.
+#[derive(Clone)]
+pub struct AsyncKeyBondingInstance {
+    /// Jobs forwarded in order to the worker by a thread waiting for its queue.
+    jobs: Sender<Job>,
+    key_bonding: KeyBonding,
+}
+
+impl AsyncKeyBondingInstance {
+    /// Create the device with `KeyBondingInstance::new` on a worker thread, queuing up to
+    /// `capacity` operations.
+    pub fn start(capacity: usize) -> Starting {
+        Starting {
+            capacity,
+            handle: None,
+        }
+    }
+    /// Send the keys from `worker`, the operations are forwarded to its queue from a thread.
+    pub fn with_worker(worker: KeyboardWorker) -> Result<AsyncKeyBondingInstance, String> {
+        let (jobs, mut queue) = mpsc::channel::<Job>(1);
+        thread::Builder::new()
+            .name("keybd_event async queue".to_string())
+            .spawn(move || {
+                while let Some(job) = queue.blocking_recv() {
+                    // A job refused by a stopped worker is dropped with its sender, its receiver
+                    // reports the stopped worker.
+                    worker.queue(job).ok();
+                }
+            })
+            .map_err(|error| error.to_string())?;
+        Ok(AsyncKeyBondingInstance {
+            jobs,
+            key_bonding: KeyBonding::default(),
+        })
+    }
+    /// Clean data of KeyBonding
+    pub fn clear(&mut self) {
+        self.key_bonding = KeyBonding::default();
+    }
+    pub fn set_keys(&mut self, keys: Vec<KeyboardKey>) {
+        self.key_bonding.keys = keys;
+    }
+    pub fn add_keys(&mut self, keys: &[KeyboardKey]) {
+        self.key_bonding.keys.extend_from_slice(keys);
+    }
+    pub fn add_key(&mut self, key: KeyboardKey) {
+        self.key_bonding.keys.push(key);
+    }
+    pub fn has_shift(&mut self, b: bool) {
+        self.key_bonding.has_shift = b;
+    }
+    pub fn has_alt(&mut self, b: bool) {
+        self.key_bonding.has_alt = b;
+    }
+    pub fn has_ctrl(&mut self, b: bool) {
+        self.key_bonding.has_ctrl = b;
+    }
+    pub fn has_rctrl(&mut self, b: bool) {
+        self.key_bonding.has_rctrl = b;
+    }
+    pub fn has_rshift(&mut self, b: bool) {
+        self.key_bonding.has_rshift = b;
+    }
+    pub fn has_altgr(&mut self, b: bool) {
+        self.key_bonding.has_altgr = b;
+    }
+    /// For launch the simulation
+    pub fn launching(&self) -> Launching {
+        self.send(Command::Launch(self.key_bonding.clone()))
+    }
+    /// Launch `key_bondings` in order.
+    pub fn launching_sequence(&self, key_bondings: Vec<KeyBonding>) -> Launching {
+        self.send(Command::Sequence(key_bondings))
+    }
+    /// Type `text` with a US keyboard layout.
+    pub fn type_text(&self, text: &str) -> Launching {
+        self.send(Command::Text(text.to_string()))
+    }
+    /// Hold the key down during `duration`, the worker thread waits during the hold.
+    pub fn hold_for(&self, key: KeyboardKey, duration: Duration) -> Launching {
+        self.send(Command::Hold(key, duration))
+    }
+    /// Wait during `duration` with the timer of the runtime, such as the 2 seconds the desktop
+    /// needs after the creation of the device. It must be called within a tokio runtime.
+    pub fn wait(duration: Duration) -> Sleep {
+        time::sleep(duration)
+    }
+    fn send(&self, command: Command) -> Launching {
+        let (done, receiver) = oneshot::channel();
+        let cancel = CancelToken::new();
+        let job = Job {
+            command,
+            cancel: cancel.clone(),
+            done: Box::new(move |completion| {
+                done.send(completion).ok();
+            }),
+        };
+        Launching {
+            reserve: Some(Box::pin(self.jobs.clone().reserve_owned())),
+            job: Some(job),
+            receiver,
+            cancel,
+        }
+    }
+}
+
+/// Creation of an `AsyncKeyBondingInstance`, on a blocking thread of the runtime.
+pub struct Starting {
+    capacity: usize,
+    handle: Option<JoinHandle<Result<KeyboardWorker, String>>>,
+}
+
+impl Future for Starting {
+    type Output = Result<AsyncKeyBondingInstance, String>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let capacity = self.capacity;
+        let handle = self
+            .handle
+            .get_or_insert_with(|| task::spawn_blocking(move || KeyboardWorker::new(capacity)));
+        Pin::new(handle).poll(cx).map(|joined| match joined {
+            Ok(worker) => worker.and_then(AsyncKeyBondingInstance::with_worker),
+            Err(error) => Err(error.to_string()),
+        })
+    }
+}
+
+/// Future of the place of a job in the queue of an `AsyncKeyBondingInstance`.
+type Reserve = Pin<Box<dyn Future<Output = Result<OwnedPermit<Job>, SendError<()>>> + Send>>;
+
+/// An operation of an `AsyncKeyBondingInstance`, queued on the first poll.
+///
+/// Dropping it cancels the operation between two of its steps.
+pub struct Launching {
+    /// Place of the job in the queue, the places are given in the order they are asked.
+    reserve: Option<Reserve>,
+    job: Option<Job>,
+    receiver: oneshot::Receiver<Completion>,
+    cancel: CancelToken,
+}
+
+impl Launching {
+    /// Cancel the operation, the future returns `progress::CANCELLED`.
+    pub fn cancel(&self) {
+        self.cancel.cancel();
+    }
+}
+
+impl Future for Launching {
+    type Output = Result<(), String>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let reserved = match self.reserve {
+            Some(ref mut reserve) => match reserve.as_mut().poll(cx) {
+                Poll::Ready(reserved) => Some(reserved),
+                Poll::Pending => return Poll::Pending,
+            },
+            None => None,
+        };
+        if let Some(reserved) = reserved {
+            self.reserve = None;
+            // Without a place, the job is dropped with its sender, the receiver reports the
+            // stopped worker.
+            if let (Ok(permit), Some(job)) = (reserved, self.job.take()) {
+                permit.send(job);
+            }
+        }
+        Pin::new(&mut self.receiver).poll(cx).map(|received| {
+            received
+                .unwrap_or_else(|_| stopped_completion())
+                .into_result()
+        })
+    }
+}
+
+impl Drop for Launching {
+    fn drop(&mut self) {
+        self.cancel.cancel();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+    use tokio::runtime::Builder;
+    use {KBPlatform, KeyBondingInstance};
+
+    struct Recording(Arc<Mutex<Vec<KeyBonding>>>);
+
+    impl KBPlatform for Recording {
+        fn run_action(&mut self, key_bonding: KeyBonding) -> Result<(), String> {
+            self.0.lock().unwrap().push(key_bonding);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn async_instance_launches_through_the_worker() {
+        let launched = Arc::new(Mutex::new(vec![]));
+        let platform = Recording(launched.clone());
+        let worker = KeyboardWorker::with_instance(1, move || {
+            KeyBondingInstance::new_with_platform(Box::new(platform))
+        })
+        .unwrap();
+        let mut kb = AsyncKeyBondingInstance::with_worker(worker).unwrap();
+        kb.has_ctrl(true);
+        kb.add_key(KeyboardKey::KeyC);
+        let runtime = Builder::new_current_thread().enable_time().build().unwrap();
+        runtime.block_on(kb.launching()).unwrap();
+        {
+            let _runtime = runtime.enter();
+            runtime.block_on(AsyncKeyBondingInstance::wait(Duration::from_millis(1)));
+        }
+        runtime.block_on(kb.type_text("ab")).unwrap();
+        assert!(runtime
+            .block_on(kb.hold_for(KeyboardKey::KeyA, Duration::from_millis(1)))
+            .is_err());
+
+        // Never polled, never sent.
+        drop(kb.type_text("c"));
+        runtime.block_on(kb.launching()).unwrap();
+
+        // The operations waiting for a place in the full queue keep their order.
+        let typed: Vec<_> = ["d", "e", "f", "g"]
+            .iter()
+            .map(|text| runtime.spawn(kb.type_text(text)))
+            .collect();
+        for typing in typed {
+            runtime.block_on(typing).unwrap().unwrap();
+        }
+        let launched = launched.lock().unwrap();
+        assert_eq!(launched.len(), 8);
+        assert_eq!(launched[1], KeyBonding::from_char('a').unwrap());
+        assert_eq!(launched[4..], KeyBonding::from_text("defg").unwrap()[..]);
+    }
+}
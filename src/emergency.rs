@@ -0,0 +1,240 @@
+//! Emergency stop of the keys sent by this crate.
+//!
+//! `stop` cancels the operations in flight in the whole process: the sequences and texts of
+//! `KeyBondingInstance`, `KeyBondingInstance::hold_for`, `timeline::Player::play` and the replays
+//! of `evemu`. They release the keys they hold and fail with `STOPPED`, the operations started
+//! after the stop run normally. The `remap::Remapper`s release the keys they hold and keep
+//! running. On Linux, a `KillSwitch` calls `stop` when its hotkey is pressed on a physical
+//! keyboard, the keyboards grabbed by a `remap::Remapper` or a `macropad::MacroPad` included.
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+#[cfg(target_os = "linux")]
+use std::sync::atomic::AtomicBool;
+#[cfg(target_os = "linux")]
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+#[cfg(target_os = "linux")]
+use std::thread::{self, JoinHandle};
+#[cfg(target_os = "linux")]
+use std::time::SystemTime;
+
+#[cfg(target_os = "linux")]
+use evdev::EvdevDevice;
+#[cfg(target_os = "linux")]
+use hotkey::{Hotkey, HotkeyListener};
+#[cfg(target_os = "linux")]
+use linux::InputEvent;
+#[cfg(target_os = "linux")]
+use recorder::KeyTracker;
+
+/// Error of the operations cancelled by the emergency stop.
+pub const STOPPED: &str = "stopped by the emergency stop";
+
+/// Longest sleep without looking at the emergency stop.
+const SLEEP_SLICE: Duration = Duration::from_millis(10);
+
+static STOPS: AtomicUsize = AtomicUsize::new(0);
+
+/// Hotkeys of the living `KillSwitch`es, by id, looked for on the keyboards grabbed by this crate.
+#[cfg(target_os = "linux")]
+static KILL_HOTKEYS: Mutex<Vec<(usize, Hotkey)>> = Mutex::new(Vec::new());
+#[cfg(target_os = "linux")]
+static NEXT_KILL_SWITCH: AtomicUsize = AtomicUsize::new(0);
+
+/// Cancel the operations in flight.
+pub fn stop() {
+    STOPS.fetch_add(1, Ordering::SeqCst);
+}
+
+/// The emergency stop, seen from an operation.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Watch {
+    stops: usize,
+}
+
+impl Watch {
+    /// Watch the stops from now.
+    pub(crate) fn start() -> Watch {
+        Watch {
+            stops: STOPS.load(Ordering::SeqCst),
+        }
+    }
+    pub(crate) fn check(&self) -> Result<(), String> {
+        if STOPS.load(Ordering::SeqCst) == self.stops {
+            Ok(())
+        } else {
+            Err(STOPPED.to_string())
+        }
+    }
+    /// Sleep during `duration`, fails as soon as the emergency stop is pressed.
+    pub(crate) fn sleep(&self, duration: Duration) -> Result<(), String> {
+        self.sleep_checked(duration, || self.check())
+    }
+    /// Sleep during `duration`, fails with the error of `check`, called between short sleeps.
+    pub(crate) fn sleep_checked<E, F>(&self, duration: Duration, check: F) -> Result<(), E>
+    where
+        F: Fn() -> Result<(), E>,
+    {
+        let end = Instant::now() + duration;
+        loop {
+            check()?;
+            let now = Instant::now();
+            if now >= end {
+                return Ok(());
+            }
+            sleep((end - now).min(SLEEP_SLICE));
+        }
+    }
+}
+
+/// A thread watching the physical keyboards and calling `stop` when a hotkey is pressed, such as
+/// Pause or Ctrl+Alt+Escape.
+///
+/// The thread ends on drop. The virtual devices of this crate are not watched. A keyboard grabbed
+/// by a `remap::Remapper` or a `macropad::MacroPad` is read by them only, they call `stop`
+/// themselves when the hotkey is pressed on it, even during a sequence or a binding.
+#[cfg(target_os = "linux")]
+pub struct KillSwitch {
+    id: usize,
+    done: Arc<AtomicBool>,
+    thread: Option<JoinHandle<Result<(), String>>>,
+}
+
+#[cfg(target_os = "linux")]
+impl KillSwitch {
+    /// Watch every physical keyboard.
+    pub fn new(hotkey: Hotkey) -> Result<KillSwitch, String> {
+        KillSwitch::start(HotkeyListener::new()?, hotkey)
+    }
+    /// Watch `devices`.
+    pub fn with_devices(devices: Vec<EvdevDevice>, hotkey: Hotkey) -> Result<KillSwitch, String> {
+        KillSwitch::start(HotkeyListener::with_devices(devices)?, hotkey)
+    }
+    fn start(mut listener: HotkeyListener, hotkey: Hotkey) -> Result<KillSwitch, String> {
+        listener.register(hotkey.clone(), stop);
+        let done = Arc::new(AtomicBool::new(false));
+        let thread_done = done.clone();
+        let thread = thread::Builder::new()
+            .name("keybd_event kill switch".to_string())
+            .spawn(move || listener.run_until(&thread_done))
+            .map_err(|error| error.to_string())?;
+        let id = NEXT_KILL_SWITCH.fetch_add(1, Ordering::SeqCst);
+        kill_hotkeys().push((id, hotkey));
+        Ok(KillSwitch {
+            id,
+            done,
+            thread: Some(thread),
+        })
+    }
+    /// Whether the keyboards are still watched, the thread ends on a read error.
+    pub fn is_watching(&self) -> bool {
+        self.thread
+            .as_ref()
+            .is_some_and(|thread| !thread.is_finished())
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl Drop for KillSwitch {
+    fn drop(&mut self) {
+        kill_hotkeys().retain(|&(id, _)| id != self.id);
+        self.done.store(true, Ordering::SeqCst);
+        if let Some(thread) = self.thread.take() {
+            thread.join().ok();
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn kill_hotkeys() -> MutexGuard<'static, Vec<(usize, Hotkey)>> {
+    KILL_HOTKEYS.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Whether one of the `transitions`, leaving `held` down, completes the hotkey of a `KillSwitch`.
+#[cfg(target_os = "linux")]
+fn completes_kill_switch(held: &[u16], transitions: &[(SystemTime, u16, bool)]) -> bool {
+    let hotkeys = kill_hotkeys();
+    transitions.iter().any(|&(_, code, pressed)| {
+        pressed && hotkeys.iter().any(|(_, hotkey)| hotkey.matches(held, code))
+    })
+}
+
+/// Feed `event` of `device`, a keyboard grabbed by this crate, to its `tracker`.
+///
+/// The `KillSwitch`es can not read a grabbed keyboard, `stop` is called here when its transitions
+/// complete the hotkey of one of them.
+#[cfg(target_os = "linux")]
+pub(crate) fn feed_grabbed(
+    tracker: &mut KeyTracker,
+    device: &EvdevDevice,
+    event: &InputEvent,
+) -> Result<Vec<(SystemTime, u16, bool)>, String> {
+    let transitions = tracker.feed(event, || device.key_state())?;
+    if completes_kill_switch(&tracker.held, &transitions) {
+        stop();
+    }
+    Ok(transitions)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn watch_fails_after_a_stop() {
+        // `stop` is not called, it would cancel the other tests running.
+        let watch = Watch::start();
+        assert!(watch.check().is_ok());
+        assert!(watch.sleep(Duration::from_millis(15)).is_ok());
+
+        let stopped = Watch {
+            stops: watch.stops.wrapping_sub(1),
+        };
+        let start = Instant::now();
+        assert_eq!(
+            stopped.sleep(Duration::from_secs(10)),
+            Err(STOPPED.to_string())
+        );
+        assert!(start.elapsed() < Duration::from_secs(1));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn kill_switch_hotkey_is_seen_on_grabbed_keyboards() {
+        use linux::{EV_KEY, EV_SYN, SYN_REPORT};
+        use {KeyBonding, KeyboardKey};
+
+        // The events of a grabbed keyboard, as `feed_grabbed` reads them, without calling `stop`.
+        let grabbed = |events: &[InputEvent]| {
+            let mut tracker = KeyTracker::default();
+            events.iter().any(|event| {
+                let transitions = tracker.feed(event, || Ok(vec![])).unwrap();
+                completes_kill_switch(&tracker.held, &transitions)
+            })
+        };
+        let pause = [
+            InputEvent::new(EV_KEY, KeyboardKey::KeyPause as u16, 1),
+            InputEvent::new(EV_SYN, SYN_REPORT, 0),
+        ];
+        let shift_pause = [
+            InputEvent::new(EV_KEY, 42, 1),
+            InputEvent::new(EV_SYN, SYN_REPORT, 0),
+            pause[0],
+            pause[1],
+        ];
+        assert!(!grabbed(&pause));
+        let kill_switch = KillSwitch::with_devices(
+            vec![],
+            Hotkey::new(KeyBonding {
+                keys: vec![KeyboardKey::KeyPause],
+                ..KeyBonding::default()
+            }),
+        )
+        .unwrap();
+        assert!(grabbed(&pause));
+        assert!(!grabbed(&shift_pause));
+        drop(kill_switch);
+        assert!(!grabbed(&pause));
+    }
+}
@@ -0,0 +1,204 @@
+//! Reading the physical input devices through `/dev/input/event*`.
+//!
+//! Reading these nodes generally needs the root user or the `input` group.
+use std::fs::{self, File, OpenOptions};
+use std::mem;
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration;
+
+use libc::{c_int, c_ulong};
+
+use linux::{
+    ioc, last_error, read_input_events, InputEvent, InputId, DEVICE_NAME, EV_KEY, IOC_READ,
+    IOC_WRITE, KEY_MAX,
+};
+
+const EVDEV_IOCTL_BASE: c_ulong = b'E' as c_ulong;
+
+const EVIOCGID: c_ulong = ioc(IOC_READ, EVDEV_IOCTL_BASE, 0x02, mem::size_of::<InputId>());
+const EVIOCGRAB: c_ulong = ioc(IOC_WRITE, EVDEV_IOCTL_BASE, 0x90, mem::size_of::<c_int>());
+const KEY_BYTES: usize = KEY_MAX as usize / 8 + 1;
+
+fn eviocgname(len: usize) -> c_ulong {
+    ioc(IOC_READ, EVDEV_IOCTL_BASE, 0x06, len)
+}
+
+fn eviocgkey(len: usize) -> c_ulong {
+    ioc(IOC_READ, EVDEV_IOCTL_BASE, 0x18, len)
+}
+
+fn eviocgbit(kind: u16, len: usize) -> c_ulong {
+    ioc(IOC_READ, EVDEV_IOCTL_BASE, 0x20 + kind as c_ulong, len)
+}
+
+/// An opened `/dev/input/event*` node.
+pub struct EvdevDevice {
+    file: File,
+    path: PathBuf,
+    name: String,
+    id: InputId,
+    keys: [u8; KEY_BYTES],
+}
+
+impl EvdevDevice {
+    /// Open the event node in non-blocking mode.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<EvdevDevice, String> {
+        let path = path.as_ref();
+        let path_str = path.to_str().unwrap_or("PATH_ERROR");
+        let file = match OpenOptions::new()
+            .read(true)
+            .custom_flags(libc::O_NONBLOCK)
+            .open(path)
+        {
+            Ok(file) => file,
+            Err(error) => return Err(format!("{}: {}", path_str, error)),
+        };
+        let fd = file.as_raw_fd();
+        let mut name = [0u8; 256];
+        if unsafe { libc::ioctl(fd, eviocgname(name.len()) as _, name.as_mut_ptr()) } < 0 {
+            return Err(last_error(path_str));
+        }
+        let end = name.iter().position(|b| *b == 0).unwrap_or(name.len());
+        let mut id = InputId::default();
+        if unsafe { libc::ioctl(fd, EVIOCGID as _, &mut id) } < 0 {
+            return Err(last_error(path_str));
+        }
+        let mut keys = [0u8; KEY_BYTES];
+        if unsafe { libc::ioctl(fd, eviocgbit(EV_KEY, KEY_BYTES) as _, keys.as_mut_ptr()) } < 0 {
+            return Err(last_error(path_str));
+        }
+        Ok(EvdevDevice {
+            file,
+            path: path.to_path_buf(),
+            name: String::from_utf8_lossy(&name[..end]).into_owned(),
+            id,
+            keys,
+        })
+    }
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+    pub fn id(&self) -> InputId {
+        self.id
+    }
+    pub fn fd(&self) -> c_int {
+        self.file.as_raw_fd()
+    }
+    /// The device reports the key `code`.
+    pub fn has_key(&self, code: u16) -> bool {
+        test_bit(&self.keys, code)
+    }
+    /// The device has the letter keys of a keyboard.
+    pub fn is_keyboard(&self) -> bool {
+        (16..26)
+            .chain(30..39)
+            .chain(44..51)
+            .all(|code| self.has_key(code))
+    }
+    /// The device is a virtual device of this crate.
+    pub fn is_own_device(&self) -> bool {
+        self.name.starts_with(DEVICE_NAME)
+    }
+    /// Key codes currently held down on the device.
+    pub fn key_state(&self) -> Result<Vec<u16>, String> {
+        let mut state = [0u8; KEY_BYTES];
+        if unsafe { libc::ioctl(self.fd(), eviocgkey(KEY_BYTES) as _, state.as_mut_ptr()) } < 0 {
+            return Err(last_error(self.path.to_str().unwrap_or("PATH_ERROR")));
+        }
+        Ok((0..KEY_MAX + 1)
+            .filter(|code| test_bit(&state, *code))
+            .collect())
+    }
+    /// Wait until no key is held on the device, before grabbing it.
+    ///
+    /// A key held during the grab would stay pressed for the other readers of the device.
+    pub fn wait_keys_released(&self) -> Result<(), String> {
+        while !self.key_state()?.is_empty() {
+            thread::sleep(Duration::from_millis(10));
+        }
+        Ok(())
+    }
+    /// Take the device for this process only (`EVIOCGRAB`), or give it back.
+    ///
+    /// The grab is also released when the device is closed.
+    pub fn grab(&mut self, grab: bool) -> Result<(), String> {
+        if unsafe { libc::ioctl(self.fd(), EVIOCGRAB as _, grab as c_int) } < 0 {
+            return Err(last_error(self.path.to_str().unwrap_or("PATH_ERROR")));
+        }
+        Ok(())
+    }
+    /// Read the pending events without blocking.
+    pub fn read_events(&mut self) -> Result<Vec<InputEvent>, String> {
+        read_input_events(&mut self.file)
+    }
+}
+
+/// Open every readable `/dev/input/event*` node.
+pub fn devices() -> Vec<EvdevDevice> {
+    let mut paths: Vec<PathBuf> = match fs::read_dir("/dev/input") {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| {
+                path.file_name()
+                    .and_then(|name| name.to_str())
+                    .is_some_and(|name| name.starts_with("event"))
+            })
+            .collect(),
+        Err(_) => vec![],
+    };
+    paths.sort();
+    paths
+        .iter()
+        .filter_map(|path| EvdevDevice::open(path).ok())
+        .collect()
+}
+
+/// Open the physical keyboards, the virtual devices of this crate are skipped.
+pub fn keyboards() -> Vec<EvdevDevice> {
+    devices()
+        .into_iter()
+        .filter(|device| device.is_keyboard() && !device.is_own_device())
+        .collect()
+}
+
+/// Key codes held down on any physical keyboard.
+pub fn held_keys() -> Result<Vec<u16>, String> {
+    let mut held = vec![];
+    for keyboard in keyboards() {
+        held.extend(keyboard.key_state()?);
+    }
+    held.sort();
+    held.dedup();
+    Ok(held)
+}
+
+/// Wait until one of `devices` has events to read, at most `timeout`.
+pub fn wait_events(devices: &[&EvdevDevice], timeout: Duration) -> Result<bool, String> {
+    let mut fds: Vec<libc::pollfd> = devices
+        .iter()
+        .map(|device| libc::pollfd {
+            fd: device.fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        })
+        .collect();
+    let timeout = timeout.as_millis().min(c_int::MAX as u128) as c_int;
+    match unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, timeout) } {
+        ready if ready < 0 => match std::io::Error::last_os_error().kind() {
+            std::io::ErrorKind::Interrupted => Ok(false),
+            _ => Err(last_error("poll")),
+        },
+        ready => Ok(ready > 0),
+    }
+}
+
+fn test_bit(bits: &[u8], bit: u16) -> bool {
+    let bit = bit as usize;
+    bit / 8 < bits.len() && bits[bit / 8] & (1 << (bit % 8)) != 0
+}
@@ -0,0 +1,472 @@
+//! Reading and writing the text format of the evemu tools (`evemu-record`, `evemu-play`).
+//!
+//! A file describes a device with the `N:`, `I:`, `P:`, `B:` and `A:` lines, then its events with
+//! the `E:` lines.
+use std::time::{Duration, Instant};
+
+use emergency::Watch;
+use linux::{
+    keyboard_builder, AbsInfo, InputId, UInputBuilder, UInputDevice, DEVICE_NAME, EV_ABS, EV_KEY,
+    EV_LED, EV_MSC, EV_REL, EV_REP, EV_SND, EV_SW, EV_SYN, UINPUT_MAX_NAME_SIZE,
+};
+
+/// Header of the files written, the version of the format of `evemu-record`.
+const HEADER: &str = "# EVEMU 1.3";
+const EV_FF: u16 = 0x15;
+/// Bytes in the bitmask of the input properties (`INPUT_PROP_CNT`).
+const PROP_BYTES: usize = 4;
+/// Bytes of the bitmasks written on each `P:` and `B:` line.
+const BYTES_PER_LINE: usize = 8;
+
+/// A device of an evemu file.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct EvemuDevice {
+    pub name: String,
+    pub id: InputId,
+    pub properties: Vec<u16>,
+    /// Event types of the device, `EV_SYN` included.
+    pub event_types: Vec<u16>,
+    /// Codes of the device, as `(type, code)`.
+    pub codes: Vec<(u16, u16)>,
+    pub abs: Vec<(u16, AbsInfo)>,
+}
+
+impl EvemuDevice {
+    /// A builder of a uinput device with the same name, id and capabilities.
+    pub fn builder(&self) -> UInputBuilder {
+        let mut builder = UInputBuilder::new(&self.name).id(self.id);
+        for kind in &self.event_types {
+            if *kind != EV_SYN && self.codes.iter().all(|&(code_kind, _)| code_kind != *kind) {
+                builder = builder.event_type(*kind);
+            }
+        }
+        for &(kind, code) in &self.codes {
+            builder = match kind {
+                EV_ABS => builder.abs(code, self.abs_info(code)),
+                _ => builder.event(kind, code),
+            };
+        }
+        for property in &self.properties {
+            builder = builder.property(*property);
+        }
+        builder
+    }
+    fn abs_info(&self, code: u16) -> AbsInfo {
+        self.abs
+            .iter()
+            .find(|&&(abs_code, _)| abs_code == code)
+            .map(|&(_, info)| info)
+            .unwrap_or_default()
+    }
+    /// Write the description of the device.
+    pub fn to_text(&self) -> String {
+        let mut text = format!("{}\n# Input device name: \"{}\"\n", HEADER, self.name);
+        text.push_str(&format!("N: {}\n", self.name));
+        text.push_str(&format!(
+            "I: {:04x} {:04x} {:04x} {:04x}\n",
+            self.id.bustype, self.id.vendor, self.id.product, self.id.version
+        ));
+        for line in bitmask(&self.properties, PROP_BYTES).chunks(BYTES_PER_LINE) {
+            text.push_str(&format!("P:{}\n", hex_bytes(line)));
+        }
+        let mut event_types = self.event_types.clone();
+        event_types.push(EV_SYN);
+        event_types.sort();
+        event_types.dedup();
+        for kind in &event_types {
+            let codes: Vec<u16> = if *kind == EV_SYN {
+                event_types.clone()
+            } else {
+                self.codes
+                    .iter()
+                    .filter(|&&(code_kind, _)| code_kind == *kind)
+                    .map(|&(_, code)| code)
+                    .collect()
+            };
+            for line in bitmask(&codes, mask_bytes(*kind)).chunks(BYTES_PER_LINE) {
+                text.push_str(&format!("B: {:02x}{}\n", kind, hex_bytes(line)));
+            }
+        }
+        let mut abs = self.abs.clone();
+        abs.sort_by_key(|&(code, _)| code);
+        for (code, info) in abs {
+            text.push_str(&format!(
+                "A: {:02x} {} {} {} {} {}\n",
+                code, info.minimum, info.maximum, info.fuzz, info.flat, info.resolution
+            ));
+        }
+        text
+    }
+}
+
+/// An event of an evemu file, at `time` from the start of the recording.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct EvemuEvent {
+    pub time: Duration,
+    pub kind: u16,
+    pub code: u16,
+    pub value: i32,
+}
+
+impl EvemuEvent {
+    pub(crate) fn to_line(self) -> String {
+        format!(
+            "E: {}.{:06} {:04x} {:04x} {:04}\n",
+            self.time.as_secs(),
+            self.time.subsec_micros(),
+            self.kind,
+            self.code,
+            self.value
+        )
+    }
+}
+
+/// The content of an evemu file.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Evemu {
+    /// The device, `None` for a file of events only.
+    pub device: Option<EvemuDevice>,
+    pub events: Vec<EvemuEvent>,
+}
+
+impl Evemu {
+    /// Read an evemu file.
+    pub fn parse(text: &str) -> Result<Evemu, String> {
+        let mut evemu = Evemu::default();
+        let mut device = EvemuDevice::default();
+        let mut has_device = false;
+        let mut property_bytes = 0;
+        let mut type_bytes: Vec<(u16, usize)> = vec![];
+        for (index, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let error = |message: &str| format!("line {}: {}", index + 1, message);
+            // The name is taken whole, it may contain a '#'.
+            if let Some(name) = line.strip_prefix("N:") {
+                device.name = name.trim().to_string();
+                has_device = true;
+                continue;
+            }
+            // A comment may follow the fields, as the decoded event of `evemu-record`.
+            let line = line.split('#').next().unwrap_or("").trim_end();
+            let (prefix, fields) = match line.find(':') {
+                Some(colon) => (
+                    &line[..colon],
+                    line[colon + 1..].split_whitespace().collect::<Vec<&str>>(),
+                ),
+                None => return Err(error("expected a prefix such as \"E:\"")),
+            };
+            match prefix {
+                "I" => {
+                    let id = parse_hex_fields(&fields).ok_or_else(|| error("invalid id"))?;
+                    if id.len() != 4 {
+                        return Err(error("invalid id"));
+                    }
+                    device.id = InputId {
+                        bustype: id[0],
+                        vendor: id[1],
+                        product: id[2],
+                        version: id[3],
+                    };
+                }
+                "P" => {
+                    let bytes = parse_bytes(&fields).ok_or_else(|| error("invalid properties"))?;
+                    device
+                        .properties
+                        .extend(bits_of(&bytes, property_bytes * 8));
+                    property_bytes += bytes.len();
+                }
+                "B" => {
+                    let bytes = parse_bytes(&fields).ok_or_else(|| error("invalid bitmask"))?;
+                    if bytes.is_empty() {
+                        return Err(error("invalid bitmask"));
+                    }
+                    let kind = bytes[0] as u16;
+                    let offset = match type_bytes.iter_mut().find(|(seen, _)| *seen == kind) {
+                        Some(seen) => {
+                            seen.1 += bytes.len() - 1;
+                            seen.1 - (bytes.len() - 1)
+                        }
+                        None => {
+                            type_bytes.push((kind, bytes.len() - 1));
+                            0
+                        }
+                    };
+                    let bits = bits_of(&bytes[1..], offset * 8);
+                    if kind == EV_SYN {
+                        device.event_types.extend(bits);
+                    } else {
+                        device
+                            .codes
+                            .extend(bits.into_iter().map(|code| (kind, code)));
+                    }
+                }
+                "A" => {
+                    let code = fields
+                        .first()
+                        .and_then(|code| u16::from_str_radix(code, 16).ok());
+                    let values: Option<Vec<i32>> = fields
+                        .iter()
+                        .skip(1)
+                        .map(|value| value.parse().ok())
+                        .collect();
+                    match (code, values) {
+                        (Some(code), Some(ref values)) if values.len() >= 4 => {
+                            device.abs.push((
+                                code,
+                                AbsInfo {
+                                    value: 0,
+                                    minimum: values[0],
+                                    maximum: values[1],
+                                    fuzz: values[2],
+                                    flat: values[3],
+                                    resolution: values.get(4).cloned().unwrap_or(0),
+                                },
+                            ));
+                        }
+                        _ => return Err(error("invalid absolute axis")),
+                    }
+                }
+                // The states of the LEDs and the switches are not restored.
+                "L" | "S" => {}
+                "E" => {
+                    let event = parse_event(&fields).ok_or_else(|| error("invalid event"))?;
+                    evemu.events.push(event);
+                }
+                _ => return Err(error(&format!("unknown prefix {}", prefix))),
+            }
+        }
+        if has_device {
+            evemu.device = Some(device);
+        }
+        Ok(evemu)
+    }
+    /// Write the evemu file, as `evemu-play` reads it.
+    pub fn to_text(&self) -> String {
+        let mut text = match self.device {
+            Some(ref device) => device.to_text(),
+            None => format!("{}\n", HEADER),
+        };
+        text.push_str("################################\n");
+        text.push_str("#      Waiting for events      #\n");
+        text.push_str("################################\n");
+        for event in &self.events {
+            text.push_str(&event.to_line());
+        }
+        text
+    }
+    /// Create a device matching the file and replay the events with their timing.
+    ///
+    /// The device is named "keybd_event" then the recorded name, the listeners of this crate skip
+    /// it as their own device. Without device in the file, or if the kernel refuses its
+    /// capabilities, the events are sent from a keyboard as the one of `LinuxKeyBD`.
+    pub fn replay(&self) -> Result<(), String> {
+        let matching = self.device.as_ref().and_then(|device| {
+            let replayed = EvemuDevice {
+                name: replay_name(&device.name),
+                ..device.clone()
+            };
+            replayed.builder().create().ok()
+        });
+        let mut device = match matching {
+            Some(device) => device,
+            None => keyboard_builder(None, false).create()?,
+        };
+        self.replay_on(&mut device)
+    }
+    /// Replay the events on `device` with their timing.
+    ///
+    /// Fails with `emergency::STOPPED` if the emergency stop is pressed during the replay, the keys
+    /// held down by the replay are released.
+    pub fn replay_on(&self, device: &mut UInputDevice) -> Result<(), String> {
+        let first = match self.events.first() {
+            Some(event) => event.time,
+            None => return Ok(()),
+        };
+        let watch = Watch::start();
+        let start = Instant::now();
+        let mut held: Vec<u16> = vec![];
+        for event in &self.events {
+            let due = start + (event.time.max(first) - first);
+            let now = Instant::now();
+            if due > now {
+                device.flush()?;
+                if let Err(error) = watch.sleep(due - now) {
+                    // The frame in progress ends with the release of the held keys.
+                    for code in held {
+                        device.write_event(EV_KEY, code, 0)?;
+                    }
+                    device.synchronize()?;
+                    device.flush()?;
+                    return Err(error);
+                }
+            }
+            if event.kind == EV_KEY {
+                held.retain(|code| *code != event.code);
+                if event.value != 0 {
+                    held.push(event.code);
+                }
+            }
+            device.write_event(event.kind, event.code, event.value)?;
+        }
+        device.flush()
+    }
+}
+
+/// The name of the device replaying the device `name`, shortened to fit in uinput.
+fn replay_name(name: &str) -> String {
+    let mut replay_name = format!("{} {}", DEVICE_NAME, name);
+    while replay_name.len() >= UINPUT_MAX_NAME_SIZE {
+        replay_name.pop();
+    }
+    replay_name
+}
+
+/// Size of the bitmask of the event type `kind`, as the kernel reports it.
+fn mask_bytes(kind: u16) -> usize {
+    match kind {
+        EV_SYN => 4,
+        EV_KEY => 96,
+        EV_REL => 2,
+        EV_ABS => 8,
+        EV_MSC => 1,
+        EV_SW => 3,
+        EV_LED => 2,
+        EV_SND => 1,
+        EV_REP => 1,
+        EV_FF => 16,
+        _ => 1,
+    }
+}
+
+fn bitmask(bits: &[u16], len: usize) -> Vec<u8> {
+    let len = bits
+        .iter()
+        .map(|bit| *bit as usize / 8 + 1)
+        .fold(len, |len, needed| len.max(needed));
+    let mut mask = vec![0u8; len];
+    for bit in bits {
+        mask[*bit as usize / 8] |= 1 << (bit % 8);
+    }
+    mask
+}
+
+fn bits_of(bytes: &[u8], offset: usize) -> Vec<u16> {
+    (0..bytes.len() * 8)
+        .filter(|bit| bytes[bit / 8] & (1 << (bit % 8)) != 0)
+        .map(|bit| (offset + bit) as u16)
+        .collect()
+}
+
+fn hex_bytes(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!(" {:02x}", byte)).collect()
+}
+
+fn parse_hex_fields(fields: &[&str]) -> Option<Vec<u16>> {
+    fields
+        .iter()
+        .map(|field| u16::from_str_radix(field, 16).ok())
+        .collect()
+}
+
+fn parse_bytes(fields: &[&str]) -> Option<Vec<u8>> {
+    fields
+        .iter()
+        .map(|field| u8::from_str_radix(field, 16).ok())
+        .collect()
+}
+
+fn parse_event(fields: &[&str]) -> Option<EvemuEvent> {
+    if fields.len() != 4 {
+        return None;
+    }
+    let mut time = fields[0].splitn(2, '.');
+    let secs: u64 = time.next()?.parse().ok()?;
+    // The fraction of a second has 6 digits in the files of `evemu-record`, "0.1" is 100ms.
+    let nanos = match time.next() {
+        None => 0,
+        Some(fraction)
+            if (1..10).contains(&fraction.len())
+                && fraction.bytes().all(|byte| byte.is_ascii_digit()) =>
+        {
+            fraction.parse::<u32>().ok()? * 10u32.pow(9 - fraction.len() as u32)
+        }
+        Some(_) => return None,
+    };
+    Some(EvemuEvent {
+        time: Duration::new(secs, nanos),
+        kind: u16::from_str_radix(fields[1], 16).ok()?,
+        code: u16::from_str_radix(fields[2], 16).ok()?,
+        value: fields[3].parse().ok()?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RECORDING: &str = "# EVEMU 1.3
+# Input device name: \"AT Translated Set 2 keyboard\"
+N: AT Translated Set 2 keyboard
+I: 0011 0001 0001 ab41
+P: 00 00 00 00 00 00 00 00
+B: 00 13 00 12 00 00 00 00 00
+B: 01 00 00 00 40 00 00 00 00
+B: 04 10 00 00 00 00 00 00 00
+B: 11 07 00 00 00 00 00 00 00
+B: 14 00 00 00 00 00 00 00 00
+################################
+#      Waiting for events      #
+################################
+E: 0.000001 0004 0004 0030	# EV_MSC / MSC_SCAN             30
+E: 0.000001 0001 001e 0001	# EV_KEY / KEY_A                1
+E: 0.000001 0000 0000 0000	# ------------ SYN_REPORT (0) ---------- +0ms
+E: 0.095120 0001 001e 0000	# EV_KEY / KEY_A                0
+E: 0.095120 0000 0000 0000	# ------------ SYN_REPORT (0) ---------- +95ms
+";
+
+    #[test]
+    fn parses_and_writes_evemu_recordings() {
+        let evemu = Evemu::parse(RECORDING).unwrap();
+        let device = evemu.device.clone().unwrap();
+        assert_eq!(device.name, "AT Translated Set 2 keyboard");
+        assert_eq!(device.id.bustype, 0x11);
+        assert_eq!(device.id.version, 0xab41);
+        assert_eq!(
+            device.event_types,
+            vec![EV_SYN, EV_KEY, EV_MSC, EV_LED, EV_REP]
+        );
+        assert_eq!(
+            device.codes,
+            vec![
+                (EV_KEY, 30),
+                (EV_MSC, 4),
+                (EV_LED, 0),
+                (EV_LED, 1),
+                (EV_LED, 2)
+            ]
+        );
+        assert_eq!(evemu.events.len(), 5);
+        assert_eq!(
+            evemu.events[3],
+            EvemuEvent {
+                time: Duration::from_micros(95_120),
+                kind: EV_KEY,
+                code: 30,
+                value: 0,
+            }
+        );
+        assert_eq!(Evemu::parse(&evemu.to_text()).unwrap(), evemu);
+        assert!(Evemu::parse("E: 0.1 0001 zz 1\n").is_err());
+        let short = Evemu::parse("N: Pad #2\nE: 0.1 0001 001e 1 # KEY_A\n").unwrap();
+        assert_eq!(short.device.unwrap().name, "Pad #2");
+        assert_eq!(short.events[0].time, Duration::from_millis(100));
+        assert_eq!(
+            replay_name(&device.name),
+            "keybd_event AT Translated Set 2 keyboard"
+        );
+        assert_eq!(replay_name(&"é".repeat(80)).len(), 78);
+    }
+}
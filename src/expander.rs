@@ -0,0 +1,413 @@
+//! Text expansion: typing a trigger such as `;sig` on a physical keyboard replaces it by its
+//! snippet.
+//!
+//! The typed keys are read from `/dev/input/event*`, so it needs the root user or the `input`
+//! group. The characters are read with a US keyboard layout, Caps Lock is not followed. The
+//! snippets may contain date and time variables, `{{date}}`, `{{time}}` or `{{date:FORMAT}}` with
+//! a `strftime` format, and a cursor marker `$|$` where the cursor is left after the expansion.
+//!
+//! The keys moving the cursor or the focus, Enter, Tab, Escape, the arrows, and the combos with
+//! Ctrl, Alt or Meta, start a new buffer. The mouse is not watched, a click does not.
+use std::ffi::CString;
+use std::mem;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use libc;
+
+use emergency::STOPPED;
+use evdev::{self, EvdevDevice};
+use recorder::KeyTracker;
+use {us_layout_char, KeyBonding, KeyBondingInstance, KeyboardKey};
+
+const SHIFT: [u16; 2] = [42, 54];
+/// Ctrl, Alt and Meta, left and right.
+const SHORTCUT_MODIFIERS: [u16; 6] = [29, 97, 56, 100, 125, 126];
+const BACKSPACE: u16 = 14;
+const ENTER: u16 = 28;
+const TAB: u16 = 15;
+const CURSOR: &str = "$|$";
+const WAIT_INTERVAL: Duration = Duration::from_millis(100);
+
+#[derive(Clone, Debug, PartialEq)]
+enum Part {
+    Text(String),
+    /// A `strftime` format of the local time.
+    Time(String),
+    Cursor,
+}
+
+/// A trigger and the text replacing it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Snippet {
+    trigger: String,
+    parts: Vec<Part>,
+}
+
+impl Snippet {
+    /// Fails if a character of `trigger` or `replacement` can not be typed, or on an unknown
+    /// variable.
+    pub fn new(trigger: &str, replacement: &str) -> Result<Snippet, String> {
+        if trigger.is_empty() {
+            return Err("empty trigger".to_string());
+        }
+        for c in trigger.chars() {
+            if c == '\n' || c == '\t' || KeyBonding::from_char(c).is_none() {
+                return Err(format!("{}: {:?} can not be in a trigger", trigger, c));
+            }
+        }
+        let mut parts = vec![];
+        let mut rest = replacement;
+        while !rest.is_empty() {
+            let variable = rest.find("{{");
+            let cursor = rest.find(CURSOR);
+            let text_end = match (variable, cursor) {
+                (Some(variable), Some(cursor)) => variable.min(cursor),
+                (Some(index), None) | (None, Some(index)) => index,
+                (None, None) => rest.len(),
+            };
+            if text_end > 0 {
+                parts.push(Part::Text(rest[..text_end].to_string()));
+                rest = &rest[text_end..];
+            } else if rest.starts_with(CURSOR) {
+                if parts.contains(&Part::Cursor) {
+                    return Err(format!("{}: several cursor markers", trigger));
+                }
+                parts.push(Part::Cursor);
+                rest = &rest[CURSOR.len()..];
+            } else {
+                let end = rest
+                    .find("}}")
+                    .ok_or_else(|| format!("{}: unclosed variable", trigger))?;
+                let format = match &rest[2..end] {
+                    "date" => "%Y-%m-%d",
+                    "time" => "%H:%M",
+                    variable if variable.starts_with("date:") => &variable[5..],
+                    variable => return Err(format!("{}: unknown variable {}", trigger, variable)),
+                };
+                parts.push(Part::Time(format.to_string()));
+                rest = &rest[end + 2..];
+            }
+        }
+        let snippet = Snippet {
+            trigger: trigger.to_string(),
+            parts,
+        };
+        snippet
+            .expand(UNIX_EPOCH)?
+            .key_bondings()
+            .map_err(|error| format!("{}: {}", trigger, error))?;
+        Ok(snippet)
+    }
+    pub fn trigger(&self) -> &str {
+        &self.trigger
+    }
+    /// The expansion of the typed trigger at the time `now`.
+    pub fn expand(&self, now: SystemTime) -> Result<Expansion, String> {
+        let mut text = String::new();
+        let mut cursor = None;
+        for part in &self.parts {
+            match *part {
+                Part::Text(ref part) => text.push_str(part),
+                Part::Time(ref format) => text.push_str(&format_local_time(now, format)?),
+                Part::Cursor => cursor = Some(text.chars().count()),
+            }
+        }
+        let length = text.chars().count();
+        Ok(Expansion {
+            erase: self.trigger.chars().count(),
+            cursor_back: cursor.map_or(0, |cursor| length - cursor),
+            text,
+        })
+    }
+}
+
+/// Format `time` as local time with a `strftime` format, in at most 255 bytes.
+fn format_local_time(time: SystemTime, format: &str) -> Result<String, String> {
+    let seconds = match time.duration_since(UNIX_EPOCH) {
+        Ok(duration) => duration.as_secs() as libc::time_t,
+        Err(_) => return Err("time before 1970".to_string()),
+    };
+    let text = format;
+    let format = CString::new(format).map_err(|error| error.to_string())?;
+    let mut buffer = [0u8; 256];
+    let written = unsafe {
+        let mut tm: libc::tm = mem::zeroed();
+        if libc::localtime_r(&seconds, &mut tm).is_null() {
+            return Err("localtime_r failed".to_string());
+        }
+        libc::strftime(
+            buffer.as_mut_ptr() as *mut libc::c_char,
+            buffer.len(),
+            format.as_ptr(),
+            &tm,
+        )
+    };
+    // `strftime` writes nothing when the result does not fit.
+    if written == 0 && !text.is_empty() {
+        return Err(format!("the time {:?} is longer than 255 bytes", text));
+    }
+    Ok(String::from_utf8_lossy(&buffer[..written]).into_owned())
+}
+
+/// What to type for replacing a trigger.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Expansion {
+    /// Number of characters of the trigger, erased with Backspace.
+    pub erase: usize,
+    pub text: String,
+    /// Number of characters the cursor goes back with Left after the text.
+    pub cursor_back: usize,
+}
+
+impl Expansion {
+    pub fn key_bondings(&self) -> Result<Vec<KeyBonding>, String> {
+        let key = |key| KeyBonding {
+            keys: vec![key],
+            ..KeyBonding::default()
+        };
+        let mut key_bondings = vec![key(KeyboardKey::KeyBACKSPACE); self.erase];
+        key_bondings.extend(KeyBonding::from_text(&self.text)?);
+        key_bondings.extend(vec![key(KeyboardKey::KeyLEFT); self.cursor_back]);
+        Ok(key_bondings)
+    }
+}
+
+/// Rolling buffer of the typed characters, matched against the triggers.
+#[derive(Clone, Debug, Default)]
+pub struct SnippetMatcher {
+    snippets: Vec<Snippet>,
+    buffer: String,
+    held: Vec<u16>,
+}
+
+impl SnippetMatcher {
+    pub fn new(snippets: Vec<Snippet>) -> SnippetMatcher {
+        SnippetMatcher {
+            snippets,
+            ..SnippetMatcher::default()
+        }
+    }
+    /// The last characters typed since the reset, up to the length of the longest trigger.
+    pub fn buffer(&self) -> &str {
+        &self.buffer
+    }
+    pub fn reset(&mut self) {
+        self.buffer.clear();
+    }
+    /// Feed a key transition at `time`, returns the expansion of a completed trigger.
+    ///
+    /// When several triggers end the buffer, the longest one is expanded.
+    pub fn feed(
+        &mut self,
+        time: SystemTime,
+        code: u16,
+        pressed: bool,
+    ) -> Result<Option<Expansion>, String> {
+        if SHIFT.contains(&code) || SHORTCUT_MODIFIERS.contains(&code) {
+            self.held.retain(|held| *held != code);
+            if pressed {
+                self.held.push(code);
+            }
+            return Ok(None);
+        }
+        if !pressed {
+            return Ok(None);
+        }
+        let shortcut = self
+            .held
+            .iter()
+            .any(|held| SHORTCUT_MODIFIERS.contains(held));
+        let shift = self.held.iter().any(|held| SHIFT.contains(held));
+        let c = match us_layout_char(code, shift) {
+            _ if shortcut || code == ENTER || code == TAB => None,
+            c => c,
+        };
+        match c {
+            Some(c) => self.buffer.push(c),
+            None if code == BACKSPACE && !shortcut => {
+                self.buffer.pop();
+                return Ok(None);
+            }
+            None => {
+                self.reset();
+                return Ok(None);
+            }
+        }
+        let longest = self
+            .snippets
+            .iter()
+            .map(|snippet| snippet.trigger.chars().count())
+            .max()
+            .unwrap_or(0);
+        let excess = self.buffer.chars().count().saturating_sub(longest);
+        match self.buffer.char_indices().nth(excess) {
+            Some((index, _)) => {
+                self.buffer.drain(..index);
+            }
+            // Every character is in excess, as without snippets.
+            None => self.buffer.clear(),
+        }
+        let snippet = self
+            .snippets
+            .iter()
+            .filter(|snippet| self.buffer.ends_with(&snippet.trigger))
+            .max_by_key(|snippet| snippet.trigger.len());
+        match snippet {
+            Some(snippet) => {
+                let expansion = snippet.expand(time)?;
+                self.reset();
+                Ok(Some(expansion))
+            }
+            None => Ok(None),
+        }
+    }
+}
+
+struct Source {
+    device: EvdevDevice,
+    tracker: KeyTracker,
+}
+
+/// Expander watching the physical keyboards and typing the snippets from a `KeyBondingInstance`.
+pub struct TextExpander {
+    sources: Vec<Source>,
+    matcher: SnippetMatcher,
+    keyboard: KeyBondingInstance,
+}
+
+impl TextExpander {
+    /// Watch every physical keyboard and type from a new `KeyBondingInstance`.
+    pub fn new(snippets: Vec<Snippet>) -> Result<TextExpander, String> {
+        let keyboards = evdev::keyboards();
+        if keyboards.is_empty() {
+            return Err("no readable keyboard in /dev/input".to_string());
+        }
+        TextExpander::with_devices(keyboards, snippets, KeyBondingInstance::new()?)
+    }
+    /// Watch `devices` and type from `keyboard`, the virtual devices of this crate are skipped.
+    ///
+    /// A keyboard grabbed by a `remap::Remapper` or a `macropad::MacroPad` is read by them only,
+    /// its triggers are not expanded. The hotkey of a `KillSwitch` pressed on it still stops an
+    /// expansion in progress.
+    pub fn with_devices(
+        devices: Vec<EvdevDevice>,
+        snippets: Vec<Snippet>,
+        keyboard: KeyBondingInstance,
+    ) -> Result<TextExpander, String> {
+        let mut sources = vec![];
+        for device in devices {
+            if device.is_own_device() {
+                continue;
+            }
+            let tracker = KeyTracker::new(device.key_state()?);
+            sources.push(Source { device, tracker });
+        }
+        Ok(TextExpander {
+            sources,
+            matcher: SnippetMatcher::new(snippets),
+            keyboard,
+        })
+    }
+    /// Read the pending events without blocking and expand the completed triggers.
+    ///
+    /// The expansion is typed once the keys of the keyboard of the trigger are released, so a
+    /// held Shift does not change it. Returns the number of expansions.
+    pub fn poll(&mut self) -> Result<usize, String> {
+        let mut count = 0;
+        for source in &mut self.sources {
+            for event in source.device.read_events()? {
+                let device = &source.device;
+                for (time, code, pressed) in source.tracker.feed(&event, || device.key_state())? {
+                    if let Some(expansion) = self.matcher.feed(time, code, pressed)? {
+                        device.wait_keys_released()?;
+                        match self.keyboard.launching_sequence(expansion.key_bondings()?) {
+                            // The emergency stop abandons the expansion, the expander keeps running.
+                            Err(ref error) if error == STOPPED => {}
+                            result => result?,
+                        }
+                        count += 1;
+                    }
+                }
+            }
+        }
+        Ok(count)
+    }
+    /// Expand the triggers until `stop` is set.
+    pub fn run_until(&mut self, stop: &AtomicBool) -> Result<(), String> {
+        while !stop.load(Ordering::SeqCst) {
+            let devices: Vec<&EvdevDevice> =
+                self.sources.iter().map(|source| &source.device).collect();
+            if evdev::wait_events(&devices, WAIT_INTERVAL)? {
+                self.poll()?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn type_keys(matcher: &mut SnippetMatcher, codes: &[u16]) -> Option<Expansion> {
+        let mut expansion = None;
+        for &code in codes {
+            for &pressed in &[true, false] {
+                if let Some(found) = matcher.feed(UNIX_EPOCH, code, pressed).unwrap() {
+                    expansion = Some(found);
+                }
+            }
+        }
+        expansion
+    }
+
+    #[test]
+    fn matcher_expands_triggers() {
+        let time = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let mut matcher = SnippetMatcher::new(vec![
+            Snippet::new("ig", "short").unwrap(),
+            Snippet::new(";sig", "Regards,\nMe$|$!").unwrap(),
+        ]);
+        // ;sg, Backspace, i, g: the longest trigger wins.
+        let expansion = type_keys(&mut matcher, &[39, 31, 34, 14, 23, 34]).unwrap();
+        assert_eq!(
+            expansion,
+            Expansion {
+                erase: 4,
+                text: "Regards,\nMe!".to_string(),
+                cursor_back: 1,
+            }
+        );
+        assert_eq!(expansion.key_bondings().unwrap().len(), 4 + 12 + 1);
+        assert_eq!(matcher.buffer(), "");
+
+        // Shift+; is ':', then Enter changes the focus.
+        matcher.feed(time, 42, true).unwrap();
+        assert!(type_keys(&mut matcher, &[39]).is_none());
+        matcher.feed(time, 42, false).unwrap();
+        assert_eq!(matcher.buffer(), ":");
+        type_keys(&mut matcher, &[39, 28]);
+        assert_eq!(matcher.buffer(), "");
+        matcher.feed(time, 29, true).unwrap();
+        assert!(type_keys(&mut matcher, &[39, 31]).is_none());
+
+        // Without snippets, nothing is kept.
+        let mut empty = SnippetMatcher::new(vec![]);
+        type_keys(&mut empty, &[30, 31]);
+        assert_eq!(empty.buffer(), "");
+
+        let snippet = Snippet::new(";y", "{{date:%Y}} {{date}}").unwrap();
+        assert_eq!(snippet.expand(time).unwrap().text.len(), 4 + 1 + 10);
+        assert!(snippet
+            .expand(time)
+            .unwrap()
+            .text
+            .starts_with("2023 2023-11-1"));
+        let long = format!("{{{{date:{}}}}}", "x".repeat(300));
+        assert!(Snippet::new(";l", &long).is_err());
+        assert!(Snippet::new(";x", "{{weather}}").is_err());
+        assert!(Snippet::new(";x", "caf\u{e9}").is_err());
+        assert!(Snippet::new("", "x").is_err());
+    }
+}
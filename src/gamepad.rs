@@ -0,0 +1,274 @@
+//! Virtual gamepad on Linux, created with the same uinput plumbing as `LinuxKeyBD`.
+use std::thread::sleep;
+use std::time::Duration;
+
+use linux::{AbsInfo, UInputBuilder, UInputDevice, DEVICE_NAME, EV_ABS, EV_KEY};
+
+pub const ABS_X: u16 = 0x00;
+pub const ABS_Y: u16 = 0x01;
+pub const ABS_Z: u16 = 0x02;
+pub const ABS_RX: u16 = 0x03;
+pub const ABS_RY: u16 = 0x04;
+pub const ABS_RZ: u16 = 0x05;
+pub const ABS_HAT0X: u16 = 0x10;
+pub const ABS_HAT0Y: u16 = 0x11;
+
+/// Buttons of the virtual gamepad, with the standard evdev gamepad codes.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum GamepadButton {
+    South = 0x130,
+    East = 0x131,
+    North = 0x133,
+    West = 0x134,
+    TL = 0x136,
+    TR = 0x137,
+    TL2 = 0x138,
+    TR2 = 0x139,
+    Select = 0x13a,
+    Start = 0x13b,
+    Mode = 0x13c,
+    ThumbL = 0x13d,
+    ThumbR = 0x13e,
+}
+
+const BUTTONS: [GamepadButton; 13] = [
+    GamepadButton::South,
+    GamepadButton::East,
+    GamepadButton::North,
+    GamepadButton::West,
+    GamepadButton::TL,
+    GamepadButton::TR,
+    GamepadButton::TL2,
+    GamepadButton::TR2,
+    GamepadButton::Select,
+    GamepadButton::Start,
+    GamepadButton::Mode,
+    GamepadButton::ThumbL,
+    GamepadButton::ThumbR,
+];
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Stick {
+    Left,
+    Right,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Trigger {
+    Left,
+    Right,
+}
+
+/// Direction of the D-pad, reported as the hat `ABS_HAT0X`/`ABS_HAT0Y`.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum DPad {
+    Centered,
+    Up,
+    Down,
+    Left,
+    Right,
+    UpLeft,
+    UpRight,
+    DownLeft,
+    DownRight,
+}
+
+/// Options of the Linux virtual gamepad.
+#[derive(Copy, Clone, Debug)]
+pub struct GamepadConfig {
+    /// Range of the stick axes, the value is the center.
+    pub stick: AbsInfo,
+    /// Range of the trigger axes, the value is the released position.
+    pub trigger: AbsInfo,
+}
+
+impl Default for GamepadConfig {
+    fn default() -> GamepadConfig {
+        GamepadConfig {
+            stick: AbsInfo {
+                value: 0,
+                minimum: -32768,
+                maximum: 32767,
+                fuzz: 16,
+                flat: 128,
+                resolution: 0,
+            },
+            trigger: AbsInfo {
+                maximum: 255,
+                ..AbsInfo::default()
+            },
+        }
+    }
+}
+
+pub struct LinuxGamepad {
+    instance: UInputDevice,
+    config: GamepadConfig,
+    sticks: [(i32, i32); 2],
+}
+
+impl LinuxGamepad {
+    /// Create a gamepad with the default ranges.
+    pub fn new() -> Result<LinuxGamepad, String> {
+        LinuxGamepad::new_with_config(GamepadConfig::default())
+    }
+    /// Create a gamepad with the given options.
+    pub fn new_with_config(config: GamepadConfig) -> Result<LinuxGamepad, String> {
+        let hat = AbsInfo {
+            minimum: -1,
+            maximum: 1,
+            ..AbsInfo::default()
+        };
+        let mut builder = UInputBuilder::new(&format!("{} gamepad", DEVICE_NAME))
+            .abs(ABS_X, config.stick)
+            .abs(ABS_Y, config.stick)
+            .abs(ABS_RX, config.stick)
+            .abs(ABS_RY, config.stick)
+            .abs(ABS_Z, config.trigger)
+            .abs(ABS_RZ, config.trigger)
+            .abs(ABS_HAT0X, hat)
+            .abs(ABS_HAT0Y, hat);
+        for button in BUTTONS.iter() {
+            builder = builder.key(*button as u16);
+        }
+        Ok(LinuxGamepad::with_device(builder.create()?, config))
+    }
+    fn with_device(instance: UInputDevice, config: GamepadConfig) -> LinuxGamepad {
+        LinuxGamepad {
+            instance,
+            config,
+            sticks: [(config.stick.value, config.stick.value); 2],
+        }
+    }
+    pub fn press(&mut self, button: GamepadButton) -> Result<(), String> {
+        self.instance.write_event(EV_KEY, button as u16, 1)?;
+        self.frame()
+    }
+    pub fn release(&mut self, button: GamepadButton) -> Result<(), String> {
+        self.instance.write_event(EV_KEY, button as u16, 0)?;
+        self.frame()
+    }
+    /// Press the button during `duration`.
+    pub fn tap(&mut self, button: GamepadButton, duration: Duration) -> Result<(), String> {
+        self.press(button)?;
+        sleep(duration);
+        self.release(button)
+    }
+    /// Move the stick, the values are clamped to the stick range.
+    pub fn set_stick(&mut self, stick: Stick, x: i32, y: i32) -> Result<(), String> {
+        let range = self.config.stick;
+        let (x, y) = (clamp(x, &range), clamp(y, &range));
+        let (code_x, code_y, index) = match stick {
+            Stick::Left => (ABS_X, ABS_Y, 0),
+            Stick::Right => (ABS_RX, ABS_RY, 1),
+        };
+        self.instance.write_event(EV_ABS, code_x, x)?;
+        self.instance.write_event(EV_ABS, code_y, y)?;
+        self.sticks[index] = (x, y);
+        self.frame()
+    }
+    /// Move the stick back to its center.
+    pub fn center_stick(&mut self, stick: Stick) -> Result<(), String> {
+        let center = self.config.stick.value;
+        self.set_stick(stick, center, center)
+    }
+    /// Move the stick from its position to `x`, `y` in `steps` frames during `duration`.
+    pub fn sweep_stick(
+        &mut self,
+        stick: Stick,
+        x: i32,
+        y: i32,
+        duration: Duration,
+        steps: u32,
+    ) -> Result<(), String> {
+        let steps = steps.max(1);
+        let (from_x, from_y) = self.sticks[match stick {
+            Stick::Left => 0,
+            Stick::Right => 1,
+        }];
+        for step in 1..steps + 1 {
+            let ratio = step as f64 / steps as f64;
+            let step_x = from_x + ((x - from_x) as f64 * ratio).round() as i32;
+            let step_y = from_y + ((y - from_y) as f64 * ratio).round() as i32;
+            self.set_stick(stick, step_x, step_y)?;
+            if step < steps {
+                sleep(duration / steps);
+            }
+        }
+        Ok(())
+    }
+    /// Set the trigger, the value is clamped to the trigger range.
+    pub fn set_trigger(&mut self, trigger: Trigger, value: i32) -> Result<(), String> {
+        let code = match trigger {
+            Trigger::Left => ABS_Z,
+            Trigger::Right => ABS_RZ,
+        };
+        let value = clamp(value, &self.config.trigger);
+        self.instance.write_event(EV_ABS, code, value)?;
+        self.frame()
+    }
+    pub fn set_dpad(&mut self, direction: DPad) -> Result<(), String> {
+        let (x, y) = match direction {
+            DPad::Centered => (0, 0),
+            DPad::Up => (0, -1),
+            DPad::Down => (0, 1),
+            DPad::Left => (-1, 0),
+            DPad::Right => (1, 0),
+            DPad::UpLeft => (-1, -1),
+            DPad::UpRight => (1, -1),
+            DPad::DownLeft => (-1, 1),
+            DPad::DownRight => (1, 1),
+        };
+        self.instance.write_event(EV_ABS, ABS_HAT0X, x)?;
+        self.instance.write_event(EV_ABS, ABS_HAT0Y, y)?;
+        self.frame()
+    }
+    fn frame(&mut self) -> Result<(), String> {
+        self.instance.synchronize()?;
+        self.instance.flush()
+    }
+}
+
+fn clamp(value: i32, range: &AbsInfo) -> i32 {
+    value.max(range.minimum).min(range.maximum)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+    use std::time::Duration;
+
+    use super::*;
+    use linux::tests::{read_events, stand_in};
+    use linux::EV_SYN;
+
+    #[test]
+    fn sweep_interpolates_and_clamps() {
+        let path = stand_in("gamepad");
+        {
+            let file = File::create(&path).unwrap();
+            let mut gamepad =
+                LinuxGamepad::with_device(UInputDevice::from_file(file), GamepadConfig::default());
+            gamepad
+                .sweep_stick(Stick::Right, 40000, -1000, Duration::from_millis(2), 2)
+                .unwrap();
+            gamepad.set_dpad(DPad::UpLeft).unwrap();
+        }
+        let events: Vec<(u16, u16, i32)> = read_events(&path)
+            .into_iter()
+            .filter(|event| event.0 != EV_SYN)
+            .map(|(kind, code, value, _, _)| (kind, code, value))
+            .collect();
+        assert_eq!(
+            events,
+            vec![
+                (EV_ABS, ABS_RX, 20000),
+                (EV_ABS, ABS_RY, -500),
+                (EV_ABS, ABS_RX, 32767),
+                (EV_ABS, ABS_RY, -1000),
+                (EV_ABS, ABS_HAT0X, -1),
+                (EV_ABS, ABS_HAT0Y, -1),
+            ]
+        );
+    }
+}
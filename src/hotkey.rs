@@ -0,0 +1,195 @@
+//! Global hotkeys read from the physical keyboards through evdev, without X11 or Wayland.
+//!
+//! The virtual devices of this crate are never watched, so the keys it sends can not trigger a
+//! hotkey. Reading `/dev/input/event*` needs the root user or the `input` group.
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+use evdev::{self, EvdevDevice};
+use linux::bonding_modifiers;
+use recorder::KeyTracker;
+use KeyBonding;
+
+/// Left and right Ctrl, Shift, Alt.
+const MODIFIER_PAIRS: [(u16, u16); 3] = [(29, 97), (42, 54), (56, 100)];
+const META: [u16; 2] = [125, 126];
+const WAIT_INTERVAL: Duration = Duration::from_millis(100);
+
+/// A combo of modifiers and keys, as a `KeyBonding`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Hotkey {
+    key_bonding: KeyBonding,
+    any_side: bool,
+}
+
+impl Hotkey {
+    /// The combo of `key_bonding`, `has_ctrl` is the left Ctrl only and `has_rctrl` the right one.
+    pub fn new(key_bonding: KeyBonding) -> Hotkey {
+        Hotkey {
+            key_bonding,
+            any_side: false,
+        }
+    }
+    /// Accept the left or the right modifier for each modifier of the combo.
+    pub fn any_side(mut self) -> Hotkey {
+        self.any_side = true;
+        self
+    }
+    /// The combo is complete when `pressed` goes down while `held` are held.
+    ///
+    /// The held modifiers must be those of the combo, a meta key held prevents any match.
+    pub fn matches(&self, held: &[u16], pressed: u16) -> bool {
+        let keys: Vec<u16> = self
+            .key_bonding
+            .keys
+            .iter()
+            .map(|key| *key as u16)
+            .collect();
+        if !keys.contains(&pressed) || !keys.iter().all(|key| held.contains(key)) {
+            return false;
+        }
+        if META.iter().any(|meta| held.contains(meta)) {
+            return false;
+        }
+        let wanted = bonding_modifiers(&self.key_bonding);
+        MODIFIER_PAIRS.iter().all(|&(left, right)| {
+            if self.any_side {
+                let want = wanted.contains(&left) || wanted.contains(&right);
+                want == (held.contains(&left) || held.contains(&right))
+            } else {
+                wanted.contains(&left) == held.contains(&left)
+                    && wanted.contains(&right) == held.contains(&right)
+            }
+        })
+    }
+}
+
+struct Source {
+    device: EvdevDevice,
+    tracker: KeyTracker,
+}
+
+/// Listener running callbacks when hotkeys are pressed on the physical keyboards.
+pub struct HotkeyListener {
+    sources: Vec<Source>,
+    hotkeys: Vec<(Hotkey, Box<dyn FnMut() + Send>)>,
+}
+
+impl HotkeyListener {
+    /// Watch every physical keyboard.
+    pub fn new() -> Result<HotkeyListener, String> {
+        let keyboards = evdev::keyboards();
+        if keyboards.is_empty() {
+            return Err("no readable keyboard in /dev/input".to_string());
+        }
+        HotkeyListener::with_devices(keyboards)
+    }
+    /// Watch `devices`, the virtual devices of this crate are skipped.
+    pub fn with_devices(devices: Vec<EvdevDevice>) -> Result<HotkeyListener, String> {
+        let mut sources = vec![];
+        for device in devices {
+            if device.is_own_device() {
+                continue;
+            }
+            let tracker = KeyTracker::new(device.key_state()?);
+            sources.push(Source { device, tracker });
+        }
+        Ok(HotkeyListener {
+            sources,
+            hotkeys: vec![],
+        })
+    }
+    /// Run `callback` each time `hotkey` is pressed.
+    pub fn register<F>(&mut self, hotkey: Hotkey, callback: F)
+    where
+        F: FnMut() + Send + 'static,
+    {
+        self.hotkeys.push((hotkey, Box::new(callback)));
+    }
+    /// Read the pending events without blocking and run the callbacks of the pressed hotkeys.
+    ///
+    /// Returns the number of callbacks run.
+    pub fn poll(&mut self) -> Result<usize, String> {
+        let mut count = 0;
+        for index in 0..self.sources.len() {
+            let events = self.sources[index].device.read_events()?;
+            for event in events {
+                let presses: Vec<u16> = {
+                    let source = &mut self.sources[index];
+                    let device = &source.device;
+                    source
+                        .tracker
+                        .feed(&event, || device.key_state())?
+                        .into_iter()
+                        .filter(|&(_, _, pressed)| pressed)
+                        .map(|(_, code, _)| code)
+                        .collect()
+                };
+                if presses.is_empty() {
+                    continue;
+                }
+                let held = self.held();
+                for code in presses {
+                    for &mut (ref hotkey, ref mut callback) in &mut self.hotkeys {
+                        if hotkey.matches(&held, code) {
+                            callback();
+                            count += 1;
+                        }
+                    }
+                }
+            }
+        }
+        Ok(count)
+    }
+    /// Keys held on any of the watched keyboards.
+    fn held(&self) -> Vec<u16> {
+        let mut held: Vec<u16> = self
+            .sources
+            .iter()
+            .flat_map(|source| source.tracker.held.iter().cloned())
+            .collect();
+        held.sort();
+        held.dedup();
+        held
+    }
+    /// Wait for the hotkeys and run their callbacks until `stop` is set.
+    pub fn run_until(&mut self, stop: &AtomicBool) -> Result<(), String> {
+        while !stop.load(Ordering::SeqCst) {
+            let devices: Vec<&EvdevDevice> =
+                self.sources.iter().map(|source| &source.device).collect();
+            if evdev::wait_events(&devices, WAIT_INTERVAL)? {
+                self.poll()?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use KeyboardKey;
+
+    #[test]
+    fn hotkeys_match_modifier_sides() {
+        let ctrl_a = Hotkey::new(KeyBonding {
+            has_ctrl: true,
+            keys: vec![KeyboardKey::KeyA],
+            ..KeyBonding::default()
+        });
+        assert!(ctrl_a.matches(&[29, 30], 30));
+        assert!(!ctrl_a.matches(&[97, 30], 30));
+        assert!(!ctrl_a.matches(&[29, 42, 30], 30));
+        assert!(!ctrl_a.matches(&[29, 125, 30], 30));
+        assert!(!ctrl_a.matches(&[29, 30, 48], 48));
+        assert!(ctrl_a.clone().any_side().matches(&[97, 30], 30));
+
+        let shift_a_b = Hotkey::new(KeyBonding {
+            has_rshift: true,
+            keys: vec![KeyboardKey::KeyA, KeyboardKey::KeyB],
+            ..KeyBonding::default()
+        });
+        assert!(!shift_a_b.matches(&[54, 30], 30));
+        assert!(shift_a_b.matches(&[54, 30, 48], 48));
+    }
+}
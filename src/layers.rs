@@ -0,0 +1,543 @@
+//! Tap-hold keys, layers, one-shot modifiers and combos, as a keyboard firmware does.
+//!
+//! `LayerEngine` is a state machine fed with the timed key events of a keyboard, for example the
+//! transitions of a grabbed keyboard, and returns the `KeyBonding`s to launch. It reads no device
+//! and no clock: `tick` gives it the time when no event comes.
+use std::time::Duration;
+
+use timeline::{Modifier, TimedKeyEvent};
+use {KeyBonding, KeyboardKey};
+
+/// What a key does while it is held down, for a tap-hold key.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Hold {
+    Modifier(Modifier),
+    /// A momentary layer.
+    Layer(usize),
+}
+
+/// What a key does on a layer.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Action {
+    /// Launch the key with the held modifiers.
+    Key(KeyboardKey),
+    /// Launch the `KeyBonding` with the held modifiers.
+    Bonding(KeyBonding),
+    /// Hold the modifier.
+    Modifier(Modifier),
+    /// `tap` when the key is released within the tapping term, `hold` when it is held longer.
+    ///
+    /// `tapping_term` replaces the tapping term of the engine for this key, for example longer
+    /// on home-row modifiers.
+    TapHold {
+        tap: KeyboardKey,
+        hold: Hold,
+        tapping_term: Option<Duration>,
+    },
+    /// Activate the layer while the key is held.
+    Momentary(usize),
+    /// Activate or deactivate the layer.
+    Toggle(usize),
+    /// Hold the modifier, or apply it to the next key only when it is tapped alone.
+    OneShot(Modifier),
+    /// Use the action of the key on the layers below.
+    Transparent,
+    /// Nothing.
+    NoOp,
+}
+
+/// Keys pressed together launching a `KeyBonding` instead of their own actions.
+#[derive(Clone, Debug, PartialEq)]
+struct Combo {
+    keys: Vec<u16>,
+    output: KeyBonding,
+}
+
+/// What a held key resolved to, applied until its release.
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Held {
+    Nothing,
+    Modifier(Modifier),
+    Layer(usize),
+    /// A one-shot modifier, with whether a key was launched while it was held.
+    OneShot(Modifier, bool),
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+struct Transition {
+    time: Duration,
+    code: u16,
+    pressed: bool,
+}
+
+enum ComboDecision {
+    Trigger(usize),
+    Wait,
+    None,
+}
+
+/// State machine of the layers, tap-hold keys, one-shot modifiers and combos.
+///
+/// The layer 0 is the base layer, a key without action on the active layers does its own key.
+#[derive(Clone, Debug)]
+pub struct LayerEngine {
+    layers: Vec<Vec<(u16, Action)>>,
+    combos: Vec<Combo>,
+    tapping_term: Duration,
+    combo_term: Duration,
+    permissive_hold: bool,
+    toggled: Vec<usize>,
+    held: Vec<(u16, Held)>,
+    one_shots: Vec<Modifier>,
+    /// Transitions waiting for a tap-hold or a combo to be decided.
+    queue: Vec<Transition>,
+}
+
+impl Default for LayerEngine {
+    fn default() -> LayerEngine {
+        LayerEngine {
+            layers: vec![vec![]],
+            combos: vec![],
+            tapping_term: Duration::from_millis(200),
+            combo_term: Duration::from_millis(50),
+            permissive_hold: false,
+            toggled: vec![],
+            held: vec![],
+            one_shots: vec![],
+            queue: vec![],
+        }
+    }
+}
+
+impl LayerEngine {
+    pub fn new() -> LayerEngine {
+        LayerEngine::default()
+    }
+    /// Time after which a held tap-hold key is a hold, 200 ms by default.
+    pub fn tapping_term(mut self, tapping_term: Duration) -> LayerEngine {
+        self.tapping_term = tapping_term;
+        self
+    }
+    /// Time between the first and the last key of a combo, 50 ms by default.
+    pub fn combo_term(mut self, combo_term: Duration) -> LayerEngine {
+        self.combo_term = combo_term;
+        self
+    }
+    /// Decide a hold as soon as another key is pressed and released during the tap-hold key.
+    pub fn permissive_hold(mut self, permissive_hold: bool) -> LayerEngine {
+        self.permissive_hold = permissive_hold;
+        self
+    }
+    /// Give `action` to `key` on `layer`.
+    pub fn map(self, layer: usize, key: KeyboardKey, action: Action) -> LayerEngine {
+        self.map_code(layer, key as u16, action)
+    }
+    /// Give `action` to the modifier key `modifier` on `layer`.
+    pub fn map_modifier(self, layer: usize, modifier: Modifier, action: Action) -> LayerEngine {
+        self.map_code(layer, modifier as u16, action)
+    }
+    fn map_code(mut self, layer: usize, code: u16, action: Action) -> LayerEngine {
+        while self.layers.len() <= layer {
+            self.layers.push(vec![]);
+        }
+        self.layers[layer].retain(|&(mapped, _)| mapped != code);
+        self.layers[layer].push((code, action));
+        self
+    }
+    /// Launch `output` when `keys` are pressed together, within the combo term.
+    pub fn combo(mut self, keys: &[KeyboardKey], output: KeyBonding) -> LayerEngine {
+        self.combos.push(Combo {
+            keys: keys.iter().map(|key| *key as u16).collect(),
+            output,
+        });
+        self
+    }
+    /// Active layers, the toggled ones and the momentary ones held.
+    pub fn active_layers(&self) -> Vec<usize> {
+        (0..self.layers.len())
+            .filter(|layer| {
+                *layer == 0
+                    || self.toggled.contains(layer)
+                    || self
+                        .held
+                        .iter()
+                        .any(|&(_, held)| held == Held::Layer(*layer))
+            })
+            .collect()
+    }
+    /// Feed a key event, the events must come in the order of their time.
+    pub fn feed(&mut self, event: TimedKeyEvent) -> Vec<KeyBonding> {
+        self.queue.push(Transition {
+            time: event.time,
+            code: event.event.code(),
+            pressed: event.event.is_press(),
+        });
+        self.process(event.time)
+    }
+    /// Let the time pass until `now` without event, for deciding the held tap-hold keys.
+    pub fn tick(&mut self, now: Duration) -> Vec<KeyBonding> {
+        self.process(now)
+    }
+    /// Time after `now` of the next decision `tick` should be called for, if a key waits one.
+    pub fn next_deadline(&self, now: Duration) -> Option<Duration> {
+        let first = self.queue.first()?;
+        let mut deadlines = vec![];
+        if self
+            .combos
+            .iter()
+            .any(|combo| combo.keys.contains(&first.code))
+        {
+            deadlines.push(first.time + self.combo_term);
+        }
+        if let Action::TapHold { tapping_term, .. } = self.action_for(first.code) {
+            deadlines.push(first.time + tapping_term.unwrap_or(self.tapping_term));
+        }
+        deadlines
+            .into_iter()
+            .filter(|deadline| *deadline > now)
+            .min()
+    }
+
+    fn process(&mut self, now: Duration) -> Vec<KeyBonding> {
+        let mut launched = vec![];
+        while let Some(first) = self.queue.first().cloned() {
+            if !first.pressed {
+                self.queue.remove(0);
+                self.release(first.code);
+                continue;
+            }
+            match self.combo_decision(now) {
+                ComboDecision::Wait => break,
+                ComboDecision::Trigger(index) => {
+                    let combo = self.combos[index].clone();
+                    for code in &combo.keys {
+                        if let Some(position) = self
+                            .queue
+                            .iter()
+                            .position(|transition| transition.code == *code)
+                        {
+                            self.queue.remove(position);
+                        }
+                        self.held.push((*code, Held::Nothing));
+                    }
+                    launched.push(self.launch(combo.output));
+                    continue;
+                }
+                ComboDecision::None => {}
+            }
+            let action = self.action_for(first.code);
+            if let Action::TapHold {
+                tap,
+                hold,
+                tapping_term,
+            } = action
+            {
+                let deadline = first.time + tapping_term.unwrap_or(self.tapping_term);
+                let release = self
+                    .queue
+                    .iter()
+                    .position(|transition| transition.code == first.code && !transition.pressed);
+                match release {
+                    Some(release) if self.queue[release].time < deadline => {
+                        self.queue.remove(release);
+                        self.queue.remove(0);
+                        launched.push(self.launch(KeyBonding {
+                            keys: vec![tap],
+                            ..KeyBonding::default()
+                        }));
+                        continue;
+                    }
+                    Some(_) => {}
+                    None if now >= deadline => {}
+                    None if self.permissive_hold && self.other_key_tapped() => {}
+                    None => break,
+                }
+                self.queue.remove(0);
+                self.held.push((
+                    first.code,
+                    match hold {
+                        Hold::Modifier(modifier) => Held::Modifier(modifier),
+                        Hold::Layer(layer) => Held::Layer(layer),
+                    },
+                ));
+                continue;
+            }
+            self.queue.remove(0);
+            let held = match action {
+                Action::Key(key) => {
+                    launched.push(self.launch(KeyBonding {
+                        keys: vec![key],
+                        ..KeyBonding::default()
+                    }));
+                    Held::Nothing
+                }
+                Action::Bonding(key_bonding) => {
+                    launched.push(self.launch(key_bonding));
+                    Held::Nothing
+                }
+                Action::Modifier(modifier) => Held::Modifier(modifier),
+                Action::Momentary(layer) => Held::Layer(layer),
+                Action::Toggle(layer) => {
+                    if self.toggled.contains(&layer) {
+                        self.toggled.retain(|toggled| *toggled != layer);
+                    } else {
+                        self.toggled.push(layer);
+                    }
+                    Held::Nothing
+                }
+                Action::OneShot(modifier) => Held::OneShot(modifier, false),
+                _ => Held::Nothing,
+            };
+            self.held.push((first.code, held));
+        }
+        launched
+    }
+
+    /// A key pressed after the first queued key is released too.
+    fn other_key_tapped(&self) -> bool {
+        let first = self.queue[0].code;
+        self.queue
+            .iter()
+            .enumerate()
+            .skip(1)
+            .any(|(index, release)| {
+                !release.pressed
+                    && release.code != first
+                    && self.queue[1..index]
+                        .iter()
+                        .any(|press| press.pressed && press.code == release.code)
+            })
+    }
+
+    fn combo_decision(&self, now: Duration) -> ComboDecision {
+        let first = self.queue[0];
+        let candidates: Vec<usize> = (0..self.combos.len())
+            .filter(|index| self.combos[*index].keys.contains(&first.code))
+            .collect();
+        if candidates.is_empty() {
+            return ComboDecision::None;
+        }
+        let end = first.time + self.combo_term;
+        let pressed: Vec<u16> = self
+            .queue
+            .iter()
+            .take_while(|transition| transition.pressed && transition.time < end)
+            .map(|transition| transition.code)
+            .collect();
+        for index in &candidates {
+            if self.combos[*index]
+                .keys
+                .iter()
+                .all(|key| pressed.contains(key))
+            {
+                return ComboDecision::Trigger(*index);
+            }
+        }
+        let possible = candidates.iter().any(|index| {
+            pressed
+                .iter()
+                .all(|code| self.combos[*index].keys.contains(code))
+        });
+        if now < end && pressed.len() == self.queue.len() && possible {
+            ComboDecision::Wait
+        } else {
+            ComboDecision::None
+        }
+    }
+
+    fn action_for(&self, code: u16) -> Action {
+        for layer in self.active_layers().into_iter().rev() {
+            match self.layers[layer]
+                .iter()
+                .find(|&&(mapped, _)| mapped == code)
+            {
+                Some((_, Action::Transparent)) | None => {}
+                Some((_, action)) => return action.clone(),
+            }
+        }
+        if let Some(modifier) = Modifier::from_code(code) {
+            return Action::Modifier(modifier);
+        }
+        match KeyboardKey::from_code(code) {
+            Some(key) => Action::Key(key),
+            None => Action::NoOp,
+        }
+    }
+
+    fn release(&mut self, code: u16) {
+        if let Some(position) = self.held.iter().position(|&(held, _)| held == code) {
+            if let (_, Held::OneShot(modifier, false)) = self.held.remove(position) {
+                if !self.one_shots.contains(&modifier) {
+                    self.one_shots.push(modifier);
+                }
+            }
+        }
+    }
+
+    /// `key_bonding` with the held modifiers and the pending one-shot modifiers.
+    fn launch(&mut self, mut key_bonding: KeyBonding) -> KeyBonding {
+        let mut modifiers: Vec<Modifier> = self.one_shots.drain(..).collect();
+        for held in self.held.iter_mut() {
+            match held.1 {
+                Held::Modifier(modifier) => modifiers.push(modifier),
+                Held::OneShot(modifier, _) => {
+                    held.1 = Held::OneShot(modifier, true);
+                    modifiers.push(modifier);
+                }
+                _ => {}
+            }
+        }
+        for modifier in modifiers {
+            modifier.apply(&mut key_bonding);
+        }
+        key_bonding
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use timeline::KeyEvent;
+
+    fn feed(engine: &mut LayerEngine, ms: u64, key: KeyboardKey, pressed: bool) -> Vec<KeyBonding> {
+        engine.feed(TimedKeyEvent {
+            time: Duration::from_millis(ms),
+            event: if pressed {
+                KeyEvent::Press(key)
+            } else {
+                KeyEvent::Release(key)
+            },
+        })
+    }
+
+    fn bonding(key: KeyboardKey, ctrl: bool, shift: bool) -> KeyBonding {
+        KeyBonding {
+            has_ctrl: ctrl,
+            has_shift: shift,
+            keys: vec![key],
+            ..KeyBonding::default()
+        }
+    }
+
+    #[test]
+    fn tap_hold_keys_decide_on_release_or_tapping_term() {
+        use KeyboardKey::*;
+        let mut engine = LayerEngine::new()
+            .map(
+                0,
+                KeyCAPSLock,
+                Action::TapHold {
+                    tap: KeyESC,
+                    hold: Hold::Modifier(Modifier::Ctrl),
+                    tapping_term: None,
+                },
+            )
+            .map(
+                0,
+                KeyF,
+                Action::TapHold {
+                    tap: KeyF,
+                    hold: Hold::Modifier(Modifier::Shift),
+                    tapping_term: Some(Duration::from_millis(300)),
+                },
+            );
+        assert!(feed(&mut engine, 0, KeyCAPSLock, true).is_empty());
+        assert_eq!(
+            engine.next_deadline(Duration::from_millis(0)),
+            Some(Duration::from_millis(200))
+        );
+        assert_eq!(
+            feed(&mut engine, 100, KeyCAPSLock, false),
+            vec![bonding(KeyESC, false, false)]
+        );
+
+        assert!(feed(&mut engine, 1000, KeyCAPSLock, true).is_empty());
+        assert!(engine.tick(Duration::from_millis(1250)).is_empty());
+        assert_eq!(
+            feed(&mut engine, 1300, KeyC, true),
+            vec![bonding(KeyC, true, false)]
+        );
+        feed(&mut engine, 1350, KeyC, false);
+        feed(&mut engine, 1400, KeyCAPSLock, false);
+
+        // Rolling over a home-row modifier types both letters.
+        assert!(feed(&mut engine, 2000, KeyF, true).is_empty());
+        assert!(feed(&mut engine, 2050, KeyJ, true).is_empty());
+        assert_eq!(
+            feed(&mut engine, 2100, KeyF, false),
+            vec![bonding(KeyF, false, false), bonding(KeyJ, false, false)]
+        );
+        feed(&mut engine, 2150, KeyJ, false);
+
+        // With permissive hold, a key tapped during the home-row modifier is modified.
+        let mut engine = engine.permissive_hold(true);
+        assert!(feed(&mut engine, 3000, KeyF, true).is_empty());
+        assert!(feed(&mut engine, 3050, KeyJ, true).is_empty());
+        assert_eq!(
+            feed(&mut engine, 3100, KeyJ, false),
+            vec![bonding(KeyJ, false, true)]
+        );
+        assert!(feed(&mut engine, 3150, KeyF, false).is_empty());
+    }
+
+    #[test]
+    fn layers_one_shots_and_combos() {
+        use KeyboardKey::*;
+        let escape = bonding(KeyESC, false, false);
+        let mut engine = LayerEngine::new()
+            .map(0, KeySPACE, Action::Momentary(1))
+            .map(0, KeyF12, Action::Toggle(2))
+            .map(0, KeyTAB, Action::OneShot(Modifier::Shift))
+            .map(1, KeyH, Action::Key(KeyLEFT))
+            .map(1, KeyJ, Action::Transparent)
+            .map(2, KeyH, Action::Key(Key0))
+            .combo(&[KeyJ, KeyK], escape.clone());
+
+        feed(&mut engine, 0, KeySPACE, true);
+        assert_eq!(engine.active_layers(), vec![0, 1]);
+        assert_eq!(
+            feed(&mut engine, 10, KeyH, true),
+            vec![bonding(KeyLEFT, false, false)]
+        );
+        feed(&mut engine, 20, KeyH, false);
+        feed(&mut engine, 30, KeySPACE, false);
+        assert_eq!(
+            feed(&mut engine, 40, KeyH, true),
+            vec![bonding(KeyH, false, false)]
+        );
+        feed(&mut engine, 50, KeyH, false);
+
+        feed(&mut engine, 60, KeyF12, true);
+        feed(&mut engine, 70, KeyF12, false);
+        assert_eq!(
+            feed(&mut engine, 80, KeyH, true),
+            vec![bonding(Key0, false, false)]
+        );
+        feed(&mut engine, 90, KeyH, false);
+
+        feed(&mut engine, 100, KeyTAB, true);
+        feed(&mut engine, 110, KeyTAB, false);
+        assert_eq!(
+            feed(&mut engine, 120, KeyA, true),
+            vec![bonding(KeyA, false, true)]
+        );
+        feed(&mut engine, 130, KeyA, false);
+        assert_eq!(
+            feed(&mut engine, 140, KeyA, true),
+            vec![bonding(KeyA, false, false)]
+        );
+        feed(&mut engine, 150, KeyA, false);
+
+        assert!(feed(&mut engine, 200, KeyJ, true).is_empty());
+        assert_eq!(feed(&mut engine, 220, KeyK, true), vec![escape]);
+        assert!(feed(&mut engine, 230, KeyJ, false).is_empty());
+        assert!(feed(&mut engine, 240, KeyK, false).is_empty());
+
+        assert!(feed(&mut engine, 300, KeyJ, true).is_empty());
+        assert_eq!(
+            engine.tick(Duration::from_millis(350)),
+            vec![bonding(KeyJ, false, false)]
+        );
+        feed(&mut engine, 360, KeyJ, false);
+    }
+}
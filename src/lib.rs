@@ -21,14 +21,20 @@
 //!        sleep(Duration::from_secs(2));
 //!    kb.has_shift(true);
 //!    kb.add_keys(&[KeyA, KeyZ]);
-//!    kb.launching();
+//!    kb.launching().unwrap();
 //!}
 //! ```
 //! <div style="text-align: center;"><img alt="keyboard image" src="https://github.com/micmonay/keybd_event-rs/raw/master/keyboard-rust.png"/></div>
 //!
+//! ## Upgrading from 0.1
+//!
+//! Since 0.2.0, `KeyBondingInstance::launching` and `KBPlatform::run_action` return a `Result<(), String>`,
+//! a key the device does not advertise is an error instead of being dropped by the kernel.
+//! Handle the result, or `unwrap` it as above, and return `Ok(())` from your own platforms.
+//!
 //! ## Linux
 //!
-//! On Linux this library use **uinput**, but generally the uinput is only for the root user.
+//! On Linux this library use **uinput** directly through its ioctls, but generally the uinput is only for the root user.
 //!
 //! The easy solution is executing on root user or change permission by `chmod`, but it is not good.
 //!
@@ -42,24 +48,121 @@
 //!echo uinput | sudo tee /etc/modules-load.d/uinput.conf
 //!```
 //!
-//! Another subtlety on Linux, it is important after creating **KeyBondingInstance**, to waiting 2 seconds before running first keyboard actions
+//! Another subtlety on Linux, it is important after creating **KeyBondingInstance**, to waiting 2 seconds before running first keyboard actions.
+//! The creation of every device already waits its event node, up to 1 second, but the desktop needs more time for opening it.
+//!
+//! By default the virtual device advertises every key. Use `LinuxKeyBD::new_with_keys` with
+//! `KeyBondingInstance::new_with_platform` for advertising only some keys, for example a numpad-only device.
+//! The module `linux` exposes `UInputBuilder` for creating any other uinput device, with its
+//! capabilities, properties and `EV_REP`.
+//!
+//! Events of a `KeyBonding` are written in one `write`, set `LinuxKeyBDConfig::max_events_per_second`
+//! with `LinuxKeyBD::new_with_config` if the X server or libinput drops keys.
+//! `KeyBondingInstance::hold_for` holds a key with autorepeat, set `LinuxKeyBDConfig::repeat` for
+//! advertising `EV_REP` with your delay and rate, otherwise the repeats are emulated.
+//! The device tracks the lock LEDs, `KeyBondingInstance::ensure_lock_state(LockKey::CapsLock, false)`
+//! turns off Caps Lock before launching if it is on.
+//! With `LinuxKeyBDConfig::held_modifiers`, launching is refused while other modifiers are held on the
+//! physical keyboards, the virtual device can not release them. It reads `/dev/input/event*`, so it needs the `input` group.
+//! Some desktops honor media and system keys only from a device dedicated to them, set
+//! `LinuxKeyBDConfig::consumer_control` for sending them from a companion "consumer control" device.
+//!
+//! The module `mouse` creates a virtual mouse, relative or absolute with `MouseConfig::screen`,
+//! with buttons, wheels and modifiers held during clicks by the companion keyboard of `MouseConfig::modifiers`.
+//! The module `gamepad` creates a virtual gamepad with buttons, sticks, triggers and D-pad.
+//! The module `touch` creates a multitouch touchscreen with tap, long press, swipe and pinch.
+//! The module `pen` creates a pen tablet drawing polylines with pressure curves and tilt.
+//! The module `switch` creates switches such as the lid, the tablet mode or the headphone jack.
+//! The module `recorder` records the keys typed on physical keyboards, as a `timeline` of key events
+//! which `timeline::key_bondings` turns into `KeyBonding`s for playing back.
+//! A `timeline::Timeline` is saved in a versioned text format, or JSON with the feature `json`,
+//! and `timeline::Player` plays it through any `KBPlatform` with speed, idle gaps, loops and offsets.
+//! On Linux the player presses and releases each key at its recorded time, keeping the holds.
+//! The module `evemu` reads and replays the captures of `evemu-record` on a matching device, and
+//! `LinuxKeyBDConfig::evemu_export` writes the events of the keyboard as a file for `evemu-play`.
+//! The module `hotkey` runs callbacks on hotkeys typed on the physical keyboards, without X11 or
+//! Wayland, the keys sent by this crate are ignored.
+//! The module `remap` grabs a physical keyboard and sends its keys again through a table mapping
+//! keys to other keys, modifiers, combos or sequences.
+//! The module `layers` turns timed key events into `KeyBonding`s with tap-hold keys, home-row
+//! modifiers, momentary and toggled layers, one-shot modifiers and combos, as a keyboard firmware.
+//! With the feature `macropad`, the module `macropad` turns a secondary keyboard into a macro pad
+//! sending `KeyBonding`s, sequences or texts, configured by a TOML file reloaded on change.
+//! The module `expander` replaces triggers typed on the physical keyboards, such as `;sig`, by snippets
+//! with date and time variables and a cursor marker.
+//! `KeyBondingInstance::type_text` types a text with a US layout, `launching_sequence` launches `KeyBonding`s.
+//! `emergency::stop` cancels the sequences, texts, holds and playbacks in flight and releases their keys,
+//! an `emergency::KillSwitch` calls it when a hotkey such as Pause is pressed on a physical keyboard,
+//! even one grabbed by a `remap::Remapper` or a `macropad::MacroPad`.
+//! `launching_sequence_with`, `type_text_with` and `timeline::Player::play_with` take a `progress::CancelToken`
+//! and an `Observer` of the steps, characters typed and ETA, and return a `Completion` telling how far they got.
+//! A `worker::KeyboardWorker` owns a `KeyBondingInstance` on a background thread, its clones queue combos,
+//! sequences and texts from any thread, run in order, each one with a `Pending` for its `Completion`.
+//! With the feature `tokio`, `asynchronous::AsyncKeyBondingInstance` is created by `start(capacity).await`,
+//! has `launching().await` and `type_text(..).await`, queued in the order they are first polled. The keys are
+//! sent from a worker thread, which also waits the holds and the delays between keys, `wait` uses the timers
+//! of the runtime.
 //!
 //! ## Darwin (MAC OS)
 //! This library depends on the frameworks Apple, I did not find a solution for cross-compilation.
 #[cfg(target_os = "macos")]
 extern crate core_graphics;
 #[cfg(target_os = "linux")]
-extern crate uinput;
+extern crate libc;
+#[cfg(any(feature = "json", feature = "macropad"))]
+extern crate serde;
+#[cfg(feature = "json")]
+extern crate serde_json;
+#[cfg(feature = "tokio")]
+extern crate tokio;
+#[cfg(feature = "macropad")]
+extern crate toml;
+
+use std::time::Duration;
+
+use progress::{CancelToken, Completion, Observer, Outcome, Run};
 
 #[cfg(target_os = "linux")]
-use linux::LinuxKeyBD;
+pub use linux::LinuxKeyBD;
 #[cfg(target_os = "macos")]
 use macos::MacOSKeyBD;
 #[cfg(target_os = "windows")]
 use windows::WindowsKeyBD;
 
+#[cfg(feature = "tokio")]
+pub mod asynchronous;
+#[cfg(target_os = "linux")]
+pub mod evdev;
+#[cfg(target_os = "linux")]
+pub mod evemu;
+#[cfg(target_os = "linux")]
+pub mod expander;
+#[cfg(target_os = "linux")]
+pub mod gamepad;
+#[cfg(target_os = "linux")]
+pub mod hotkey;
+#[cfg(target_os = "linux")]
+pub mod linux;
+#[cfg(all(target_os = "linux", feature = "macropad"))]
+pub mod macropad;
+#[cfg(target_os = "linux")]
+pub mod mouse;
+#[cfg(target_os = "linux")]
+pub mod pen;
 #[cfg(target_os = "linux")]
-mod linux;
+pub mod recorder;
+#[cfg(target_os = "linux")]
+pub mod remap;
+#[cfg(target_os = "linux")]
+pub mod switch;
+#[cfg(target_os = "linux")]
+pub mod touch;
+
+pub mod emergency;
+pub mod layers;
+pub mod progress;
+pub mod timeline;
+pub mod worker;
 
 #[cfg(target_os = "macos")]
 mod macos;
@@ -68,7 +171,7 @@ mod macos;
 mod windows;
 
 /// Contain all Keyboard key compatible
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq)]
 pub enum KeyboardKey {
     KeySP1 = 41,
     KeySP2 = 12,
@@ -137,6 +240,7 @@ pub enum KeyboardKey {
     KeyF12 = 88,
     KeyNUMLock = 69,
     KeyScrollLock = 70,
+    KeyPause = 119,
     KeyRESERVED = 0,
     KeyBACKSPACE = 14,
     KeyTAB = 15,
@@ -163,21 +267,221 @@ pub enum KeyboardKey {
     KeyKPEqual = 117,
     KeyKPPlusMinus = 118,
     KeyKPComma = 121,
+    KeyMute = 113,
+    KeyVolumeDown = 114,
+    KeyVolumeUp = 115,
+    KeyPlayPause = 164,
+    KeyStopCD = 166,
+    KeyPreviousSong = 165,
+    KeyNextSong = 163,
+    KeyCalc = 140,
+    KeyMail = 155,
+    KeyBookmarks = 156,
+    KeyBack = 158,
+    KeyForward = 159,
+    KeyHomePage = 172,
+    KeyRefresh = 173,
+    KeySearch = 217,
+    KeyPower = 116,
+    KeySleep = 142,
+    KeyWakeUp = 143,
+}
+
+/// Media, browser and system keys, from the consumer and system control HID collections.
+pub const CONSUMER_CONTROL_KEYS: [KeyboardKey; 18] = [
+    KeyboardKey::KeyMute,
+    KeyboardKey::KeyVolumeDown,
+    KeyboardKey::KeyVolumeUp,
+    KeyboardKey::KeyPlayPause,
+    KeyboardKey::KeyStopCD,
+    KeyboardKey::KeyPreviousSong,
+    KeyboardKey::KeyNextSong,
+    KeyboardKey::KeyCalc,
+    KeyboardKey::KeyMail,
+    KeyboardKey::KeyBookmarks,
+    KeyboardKey::KeyBack,
+    KeyboardKey::KeyForward,
+    KeyboardKey::KeyHomePage,
+    KeyboardKey::KeyRefresh,
+    KeyboardKey::KeySearch,
+    KeyboardKey::KeyPower,
+    KeyboardKey::KeySleep,
+    KeyboardKey::KeyWakeUp,
+];
+
+impl KeyboardKey {
+    /// The key is a media, browser or system key.
+    pub fn is_consumer_control(&self) -> bool {
+        CONSUMER_CONTROL_KEYS
+            .iter()
+            .any(|key| *key as u8 == *self as u8)
+    }
+    /// The key of the Linux key code `code`, the modifiers have no `KeyboardKey`.
+    pub fn from_code(code: u16) -> Option<KeyboardKey> {
+        match code {
+            41 => Some(KeyboardKey::KeySP1),
+            12 => Some(KeyboardKey::KeySP2),
+            13 => Some(KeyboardKey::KeySP3),
+            26 => Some(KeyboardKey::KeySP4),
+            27 => Some(KeyboardKey::KeySP5),
+            39 => Some(KeyboardKey::KeySP6),
+            40 => Some(KeyboardKey::KeySP7),
+            43 => Some(KeyboardKey::KeySP8),
+            51 => Some(KeyboardKey::KeySP9),
+            52 => Some(KeyboardKey::KeySP10),
+            53 => Some(KeyboardKey::KeySP11),
+            86 => Some(KeyboardKey::KeySP12),
+            103 => Some(KeyboardKey::KeyUP),
+            108 => Some(KeyboardKey::KeyDOWN),
+            105 => Some(KeyboardKey::KeyLEFT),
+            106 => Some(KeyboardKey::KeyRIGHT),
+            1 => Some(KeyboardKey::KeyESC),
+            2 => Some(KeyboardKey::Key1),
+            3 => Some(KeyboardKey::Key2),
+            4 => Some(KeyboardKey::Key3),
+            5 => Some(KeyboardKey::Key4),
+            6 => Some(KeyboardKey::Key5),
+            7 => Some(KeyboardKey::Key6),
+            8 => Some(KeyboardKey::Key7),
+            9 => Some(KeyboardKey::Key8),
+            10 => Some(KeyboardKey::Key9),
+            11 => Some(KeyboardKey::Key0),
+            16 => Some(KeyboardKey::KeyQ),
+            17 => Some(KeyboardKey::KeyW),
+            18 => Some(KeyboardKey::KeyE),
+            19 => Some(KeyboardKey::KeyR),
+            20 => Some(KeyboardKey::KeyT),
+            21 => Some(KeyboardKey::KeyY),
+            22 => Some(KeyboardKey::KeyU),
+            23 => Some(KeyboardKey::KeyI),
+            24 => Some(KeyboardKey::KeyO),
+            25 => Some(KeyboardKey::KeyP),
+            30 => Some(KeyboardKey::KeyA),
+            31 => Some(KeyboardKey::KeyS),
+            32 => Some(KeyboardKey::KeyD),
+            33 => Some(KeyboardKey::KeyF),
+            34 => Some(KeyboardKey::KeyG),
+            35 => Some(KeyboardKey::KeyH),
+            36 => Some(KeyboardKey::KeyJ),
+            37 => Some(KeyboardKey::KeyK),
+            38 => Some(KeyboardKey::KeyL),
+            44 => Some(KeyboardKey::KeyZ),
+            45 => Some(KeyboardKey::KeyX),
+            46 => Some(KeyboardKey::KeyC),
+            47 => Some(KeyboardKey::KeyV),
+            48 => Some(KeyboardKey::KeyB),
+            49 => Some(KeyboardKey::KeyN),
+            50 => Some(KeyboardKey::KeyM),
+            59 => Some(KeyboardKey::KeyF1),
+            60 => Some(KeyboardKey::KeyF2),
+            61 => Some(KeyboardKey::KeyF3),
+            62 => Some(KeyboardKey::KeyF4),
+            63 => Some(KeyboardKey::KeyF5),
+            64 => Some(KeyboardKey::KeyF6),
+            65 => Some(KeyboardKey::KeyF7),
+            66 => Some(KeyboardKey::KeyF8),
+            67 => Some(KeyboardKey::KeyF9),
+            68 => Some(KeyboardKey::KeyF10),
+            87 => Some(KeyboardKey::KeyF11),
+            88 => Some(KeyboardKey::KeyF12),
+            69 => Some(KeyboardKey::KeyNUMLock),
+            70 => Some(KeyboardKey::KeyScrollLock),
+            119 => Some(KeyboardKey::KeyPause),
+            0 => Some(KeyboardKey::KeyRESERVED),
+            14 => Some(KeyboardKey::KeyBACKSPACE),
+            15 => Some(KeyboardKey::KeyTAB),
+            28 => Some(KeyboardKey::KeyENTER),
+            57 => Some(KeyboardKey::KeySPACE),
+            58 => Some(KeyboardKey::KeyCAPSLock),
+            82 => Some(KeyboardKey::KeyKP0),
+            79 => Some(KeyboardKey::KeyKP1),
+            80 => Some(KeyboardKey::KeyKP2),
+            81 => Some(KeyboardKey::KeyKP3),
+            75 => Some(KeyboardKey::KeyKP4),
+            76 => Some(KeyboardKey::KeyKP5),
+            77 => Some(KeyboardKey::KeyKP6),
+            71 => Some(KeyboardKey::KeyKP7),
+            72 => Some(KeyboardKey::KeyKP8),
+            73 => Some(KeyboardKey::KeyKP9),
+            74 => Some(KeyboardKey::KeyKPMinus),
+            78 => Some(KeyboardKey::KeyKPPlus),
+            83 => Some(KeyboardKey::KeyKPDot),
+            95 => Some(KeyboardKey::KeyKPJPComma),
+            96 => Some(KeyboardKey::KeyKPEnter),
+            98 => Some(KeyboardKey::KeyKPSlash),
+            55 => Some(KeyboardKey::KeyKPAsterisk),
+            117 => Some(KeyboardKey::KeyKPEqual),
+            118 => Some(KeyboardKey::KeyKPPlusMinus),
+            121 => Some(KeyboardKey::KeyKPComma),
+            113 => Some(KeyboardKey::KeyMute),
+            114 => Some(KeyboardKey::KeyVolumeDown),
+            115 => Some(KeyboardKey::KeyVolumeUp),
+            164 => Some(KeyboardKey::KeyPlayPause),
+            166 => Some(KeyboardKey::KeyStopCD),
+            165 => Some(KeyboardKey::KeyPreviousSong),
+            163 => Some(KeyboardKey::KeyNextSong),
+            140 => Some(KeyboardKey::KeyCalc),
+            155 => Some(KeyboardKey::KeyMail),
+            156 => Some(KeyboardKey::KeyBookmarks),
+            158 => Some(KeyboardKey::KeyBack),
+            159 => Some(KeyboardKey::KeyForward),
+            172 => Some(KeyboardKey::KeyHomePage),
+            173 => Some(KeyboardKey::KeyRefresh),
+            217 => Some(KeyboardKey::KeySearch),
+            116 => Some(KeyboardKey::KeyPower),
+            142 => Some(KeyboardKey::KeySleep),
+            143 => Some(KeyboardKey::KeyWakeUp),
+            _ => None,
+        }
+    }
+}
+
+/// Keys toggling a lock and its LED.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum LockKey {
+    CapsLock,
+    NumLock,
+    ScrollLock,
 }
 
 /// All platform need implement this trait.
 pub trait KBPlatform {
-    fn run_action(&mut self, key_bonding: KeyBonding);
+    fn run_action(&mut self, key_bonding: KeyBonding) -> Result<(), String>;
+    /// Hold the key down during `duration`, with autorepeat as a physical key.
+    fn hold_key(&mut self, _key: KeyboardKey, _duration: Duration) -> Result<(), String> {
+        Err("holding a key is not supported on this platform".to_string())
+    }
+    /// Whether `key_transition` is supported, `timeline::Player` then plays each recorded press
+    /// and release at its own time instead of tapping the pressed keys with `run_action`.
+    fn has_key_transitions(&self) -> bool {
+        false
+    }
+    /// Press the key or the modifier of the Linux key code `code` if `pressed`, else release it.
+    fn key_transition(&mut self, _code: u16, _pressed: bool) -> Result<(), String> {
+        Err("sending key transitions is not supported on this platform".to_string())
+    }
+    /// Read the state of the lock from its LED.
+    fn lock_state(&mut self, _lock: LockKey) -> Result<bool, String> {
+        Err("reading lock state is not supported on this platform".to_string())
+    }
+    /// Toggle the lock if its state is not `on`.
+    ///
+    /// Fails if the lock must be toggled and the device does not advertise its key, as a device
+    /// of `LinuxKeyBD::new_with_keys` without it.
+    fn set_lock_state(&mut self, _lock: LockKey, _on: bool) -> Result<(), String> {
+        Err("setting lock state is not supported on this platform".to_string())
+    }
 }
 
 /// Use for create and run the simulation.
 pub struct KeyBondingInstance {
     key_bonding: KeyBonding,
     platform: Box<dyn KBPlatform>,
+    lock_states: Vec<(LockKey, bool)>,
 }
 
 /// Data information for platform.
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Default, PartialEq)]
 pub struct KeyBonding {
     pub has_ctrl: bool,
     pub has_alt: bool,
@@ -188,14 +492,69 @@ pub struct KeyBonding {
     pub keys: Vec<KeyboardKey>,
 }
 
+/// Characters of the keys on a US keyboard layout: characters, shifted characters, first key code.
+const US_LAYOUT_ROWS: [(&str, &str, u16); 4] = [
+    ("1234567890-=", "!@#$%^&*()_+", 2),
+    ("qwertyuiop[]", "QWERTYUIOP{}", 16),
+    ("asdfghjkl;'`", "ASDFGHJKL:\"~", 30),
+    ("\\zxcvbnm,./", "|ZXCVBNM<>?", 43),
+];
+
+impl KeyBonding {
+    /// The `KeyBonding` typing `c` with a US keyboard layout.
+    pub fn from_char(c: char) -> Option<KeyBonding> {
+        let (code, shift) = match c {
+            ' ' => (57, false),
+            '\n' => (28, false),
+            '\t' => (15, false),
+            _ => US_LAYOUT_ROWS.iter().find_map(|&(plain, shifted, first)| {
+                if let Some(index) = plain.chars().position(|key| key == c) {
+                    Some((first + index as u16, false))
+                } else {
+                    shifted
+                        .chars()
+                        .position(|key| key == c)
+                        .map(|index| (first + index as u16, true))
+                }
+            })?,
+        };
+        Some(KeyBonding {
+            has_shift: shift,
+            keys: vec![KeyboardKey::from_code(code)?],
+            ..KeyBonding::default()
+        })
+    }
+    /// The `KeyBonding`s typing `text` with a US keyboard layout.
+    pub fn from_text(text: &str) -> Result<Vec<KeyBonding>, String> {
+        text.chars()
+            .map(|c| KeyBonding::from_char(c).ok_or_else(|| format!("no key types {:?}", c)))
+            .collect()
+    }
+}
+
+/// The character typed by the key `code` with a US keyboard layout.
+pub(crate) fn us_layout_char(code: u16, shift: bool) -> Option<char> {
+    match code {
+        57 => return Some(' '),
+        28 => return Some('\n'),
+        15 => return Some('\t'),
+        _ => {}
+    }
+    US_LAYOUT_ROWS.iter().find_map(|&(plain, shifted, first)| {
+        let index = code.checked_sub(first)? as usize;
+        if shift {
+            shifted.chars().nth(index)
+        } else {
+            plain.chars().nth(index)
+        }
+    })
+}
+
 impl KeyBondingInstance {
     /// Default function for create a new instance of KeyBondingInstance.
     pub fn new() -> Result<KeyBondingInstance, String> {
-        let platform = match KeyBondingInstance::get_platform() {
-            Ok(p) => p,
-            Err(error) => return Err(error),
-        };
-        return KeyBondingInstance::new_with_platform(platform);
+        let platform = KeyBondingInstance::get_platform()?;
+        KeyBondingInstance::new_with_platform(platform)
     }
     /// For create new KeyBondingInstance with specific platform.
     pub fn new_with_platform(platform: Box<dyn KBPlatform>) -> Result<KeyBondingInstance, String> {
@@ -210,6 +569,7 @@ impl KeyBondingInstance {
                 keys: vec![],
             },
             platform,
+            lock_states: vec![],
         })
     }
     fn get_platform() -> Result<Box<dyn KBPlatform>, String> {
@@ -265,21 +625,87 @@ impl KeyBondingInstance {
     pub fn has_altgr(&mut self, b: bool) {
         self.key_bonding.has_altgr = b;
     }
+    /// Set the lock to `on` before each launching, the lock is toggled only when needed.
+    ///
+    /// The launching fails when the lock must be toggled with a key the device does not advertise.
+    pub fn ensure_lock_state(&mut self, lock: LockKey, on: bool) {
+        self.lock_states.retain(|&(other, _)| other != lock);
+        self.lock_states.push((lock, on));
+    }
+    /// Read the state of the lock from its LED.
+    pub fn lock_state(&mut self, lock: LockKey) -> Result<bool, String> {
+        self.platform.lock_state(lock)
+    }
     /// For launch the simulation
-    pub fn launching(&mut self) {
-        self.platform.run_action(self.key_bonding.clone());
+    pub fn launching(&mut self) -> Result<(), String> {
+        for &(lock, on) in self.lock_states.iter() {
+            self.platform.set_lock_state(lock, on)?;
+        }
+        self.platform.run_action(self.key_bonding.clone())
+    }
+    /// Launch `key_bondings` in order, the locks are set once before the first one.
+    ///
+    /// Fails with `emergency::STOPPED` if the emergency stop is pressed before the last one.
+    pub fn launching_sequence(&mut self, key_bondings: Vec<KeyBonding>) -> Result<(), String> {
+        self.launching_sequence_with(key_bondings, &CancelToken::new(), &mut ())
+            .into_result()
+    }
+    /// Launch `key_bondings` in order until `cancel` is cancelled, reporting to `observer`.
+    pub fn launching_sequence_with(
+        &mut self,
+        key_bondings: Vec<KeyBonding>,
+        cancel: &CancelToken,
+        observer: &mut dyn Observer,
+    ) -> Completion {
+        let mut run = Run::new(cancel, observer, Some(key_bondings.len()));
+        let mut result = Ok(());
+        for &(lock, on) in self.lock_states.iter() {
+            if let Err(error) = self.platform.set_lock_state(lock, on) {
+                result = Err(Outcome::Failed(error));
+                break;
+            }
+        }
+        let platform = &mut self.platform;
+        for key_bonding in key_bondings {
+            if result.is_err() {
+                break;
+            }
+            result = run.step(key_bonding, |key_bonding| platform.run_action(key_bonding));
+        }
+        run.finish(result)
+    }
+    /// Type `text` with a US keyboard layout, nothing is typed if a character has no key.
+    pub fn type_text(&mut self, text: &str) -> Result<(), String> {
+        let key_bondings = KeyBonding::from_text(text)?;
+        self.launching_sequence(key_bondings)
+    }
+    /// Type `text` until `cancel` is cancelled, reporting each character to `observer`.
+    pub fn type_text_with(
+        &mut self,
+        text: &str,
+        cancel: &CancelToken,
+        observer: &mut dyn Observer,
+    ) -> Completion {
+        match KeyBonding::from_text(text) {
+            Ok(key_bondings) => self.launching_sequence_with(key_bondings, cancel, observer),
+            Err(error) => Run::new(cancel, observer, Some(0)).finish(Err(Outcome::Failed(error))),
+        }
+    }
+    /// Hold the key down during `duration`, the key repeats like a physical key held down.
+    pub fn hold_for(&mut self, key: KeyboardKey, duration: Duration) -> Result<(), String> {
+        self.platform.hold_key(key, duration)
     }
 }
 
 #[cfg(test)]
 mod tests {
     #[cfg(target_os = "linux")]
-    use std::thread::{sleep, Thread};
+    use std::thread::sleep;
     #[cfg(target_os = "linux")]
     use std::time::Duration;
 
-    use KeyBondingInstance;
     use KeyboardKey::*;
+    use {KeyBonding, KeyBondingInstance, KeyboardKey};
 
     #[test]
     fn it_works() {
@@ -288,6 +714,30 @@ mod tests {
         sleep(Duration::from_secs(2));
         kb.has_shift(true);
         kb.add_keys(&[KeyA, KeyZ]);
-        kb.launching();
+        kb.launching().unwrap();
+    }
+
+    #[test]
+    fn text_uses_us_layout() {
+        let key_bondings = KeyBonding::from_text("a Z?\n").unwrap();
+        let keys: Vec<(KeyboardKey, bool)> = key_bondings
+            .iter()
+            .map(|key_bonding| (key_bonding.keys[0], key_bonding.has_shift))
+            .collect();
+        assert_eq!(
+            keys,
+            vec![
+                (KeyA, false),
+                (KeySPACE, false),
+                (KeyZ, true),
+                (KeySP11, true),
+                (KeyENTER, false)
+            ]
+        );
+        assert_eq!(KeyBonding::from_char('\\').unwrap().keys, vec![KeySP8]);
+        assert!(KeyBonding::from_text("é").is_err());
+        assert_eq!(::us_layout_char(KeySP11 as u16, true), Some('?'));
+        assert_eq!(::us_layout_char(KeyQ as u16, false), Some('q'));
+        assert_eq!(::us_layout_char(KeyLEFT as u16, false), None);
     }
 }
@@ -1,118 +1,1144 @@
-use std::fs::File;
-use std::io::ErrorKind;
-use std::path::Path;
+use std::ffi::CString;
+use std::fs::{self, File, OpenOptions};
+use std::io::{ErrorKind, Read, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::thread::sleep;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use std::{mem, slice};
 
-use uinput::Device;
+use libc::{c_int, c_ulong, timeval};
 
-use {KBPlatform, KeyBonding};
+use emergency::Watch;
+use evdev;
+use evemu::{Evemu, EvemuDevice, EvemuEvent};
+use {KBPlatform, KeyBonding, KeyboardKey, LockKey, CONSUMER_CONTROL_KEYS};
 
-const EV_SYN: i32 = 0x00;
-const EV_KEY: i32 = 0x01;
+/// Name of the virtual devices created by this crate.
+pub const DEVICE_NAME: &str = "keybd_event";
+
+pub const EV_SYN: u16 = 0x00;
+pub const EV_KEY: u16 = 0x01;
+pub const EV_REL: u16 = 0x02;
+pub const EV_ABS: u16 = 0x03;
+pub const EV_MSC: u16 = 0x04;
+pub const EV_SW: u16 = 0x05;
+pub const EV_LED: u16 = 0x11;
+pub const EV_SND: u16 = 0x12;
+pub const EV_REP: u16 = 0x14;
+
+pub const SYN_REPORT: u16 = 0;
+pub const SYN_DROPPED: u16 = 3;
+
+pub const MSC_SCAN: u16 = 0x04;
+
+pub const LED_NUML: u16 = 0x00;
+pub const LED_CAPSL: u16 = 0x01;
+pub const LED_SCROLLL: u16 = 0x02;
+
+pub const REP_DELAY: u16 = 0x00;
+pub const REP_PERIOD: u16 = 0x01;
+
+pub const INPUT_PROP_POINTER: u16 = 0x00;
+pub const INPUT_PROP_DIRECT: u16 = 0x01;
+
+pub const BUS_USB: u16 = 0x03;
+pub const BUS_VIRTUAL: u16 = 0x06;
+
+/// Highest key code known by the kernel (`KEY_MAX`).
+pub const KEY_MAX: u16 = 0x2ff;
+/// Number of absolute axes (`ABS_CNT`).
+pub const ABS_CNT: usize = 0x40;
+
+pub(crate) const UINPUT_MAX_NAME_SIZE: usize = 80;
 
-const K_LEFT_CTRL: u8 = 29;
 const K_RIGHT_CTRL: u8 = 97;
 const K_CTRL: u8 = 29;
-const K_LEFT_SHIFT: u8 = 42;
 const K_RIGHT_SHIFT: u8 = 54;
 const K_SHIFT: u8 = 42;
-const K_LEFT_ALT: u8 = 56;
 const K_RIGHT_ALT: u8 = 100;
 const K_ALT: u8 = 56;
+const K_LEFT_META: u8 = 125;
+const K_RIGHT_META: u8 = 126;
+
+/// Modifier keys used by `KeyBonding` and the meta keys, always advertised by the device.
+pub(crate) const MODIFIERS: [u8; 8] = [
+    K_CTRL,
+    K_RIGHT_CTRL,
+    K_SHIFT,
+    K_RIGHT_SHIFT,
+    K_ALT,
+    K_RIGHT_ALT,
+    K_LEFT_META,
+    K_RIGHT_META,
+];
+
+pub(crate) const IOC_WRITE: c_ulong = 1;
+pub(crate) const IOC_READ: c_ulong = 2;
+const UINPUT_IOCTL_BASE: c_ulong = b'U' as c_ulong;
+
+/// Request number of the ioctl `nr` of the driver `base`, as the `_IOC` macro.
+pub(crate) const fn ioc(dir: c_ulong, base: c_ulong, nr: c_ulong, size: usize) -> c_ulong {
+    (dir << 30) | ((size as c_ulong) << 16) | (base << 8) | nr
+}
+
+const UI_DEV_CREATE: c_ulong = ioc(0, UINPUT_IOCTL_BASE, 1, 0);
+const UI_DEV_DESTROY: c_ulong = ioc(0, UINPUT_IOCTL_BASE, 2, 0);
+const UI_DEV_SETUP: c_ulong = ioc(
+    IOC_WRITE,
+    UINPUT_IOCTL_BASE,
+    3,
+    mem::size_of::<UinputSetup>(),
+);
+const UI_ABS_SETUP: c_ulong = ioc(
+    IOC_WRITE,
+    UINPUT_IOCTL_BASE,
+    4,
+    mem::size_of::<UinputAbsSetup>(),
+);
+const UI_GET_VERSION: c_ulong = ioc(IOC_READ, UINPUT_IOCTL_BASE, 45, mem::size_of::<u32>());
+const UI_SET_EVBIT: c_ulong = ioc(IOC_WRITE, UINPUT_IOCTL_BASE, 100, mem::size_of::<c_int>());
+const UI_SET_KEYBIT: c_ulong = ioc(IOC_WRITE, UINPUT_IOCTL_BASE, 101, mem::size_of::<c_int>());
+const UI_SET_RELBIT: c_ulong = ioc(IOC_WRITE, UINPUT_IOCTL_BASE, 102, mem::size_of::<c_int>());
+const UI_SET_ABSBIT: c_ulong = ioc(IOC_WRITE, UINPUT_IOCTL_BASE, 103, mem::size_of::<c_int>());
+const UI_SET_MSCBIT: c_ulong = ioc(IOC_WRITE, UINPUT_IOCTL_BASE, 104, mem::size_of::<c_int>());
+const UI_SET_LEDBIT: c_ulong = ioc(IOC_WRITE, UINPUT_IOCTL_BASE, 105, mem::size_of::<c_int>());
+const UI_SET_SNDBIT: c_ulong = ioc(IOC_WRITE, UINPUT_IOCTL_BASE, 106, mem::size_of::<c_int>());
+const UI_SET_PHYS: c_ulong = ioc(
+    IOC_WRITE,
+    UINPUT_IOCTL_BASE,
+    108,
+    mem::size_of::<*const u8>(),
+);
+const UI_SET_SWBIT: c_ulong = ioc(IOC_WRITE, UINPUT_IOCTL_BASE, 109, mem::size_of::<c_int>());
+const UI_SET_PROPBIT: c_ulong = ioc(IOC_WRITE, UINPUT_IOCTL_BASE, 110, mem::size_of::<c_int>());
+
+/// First uinput version supporting `UI_DEV_SETUP` and `UI_GET_SYSNAME`.
+const UINPUT_VERSION_SETUP: u32 = 5;
+
+fn ui_get_sysname(len: usize) -> c_ulong {
+    ioc(IOC_READ, UINPUT_IOCTL_BASE, 44, len)
+}
+
+/// Linux `struct input_event`.
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub struct InputEvent {
+    pub time: timeval,
+    pub kind: u16,
+    pub code: u16,
+    pub value: i32,
+}
+
+/// Linux `struct input_id`.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct InputId {
+    pub bustype: u16,
+    pub vendor: u16,
+    pub product: u16,
+    pub version: u16,
+}
+
+/// Linux `struct input_absinfo`, the range of an absolute axis.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct AbsInfo {
+    pub value: i32,
+    pub minimum: i32,
+    pub maximum: i32,
+    pub fuzz: i32,
+    pub flat: i32,
+    pub resolution: i32,
+}
+
+#[repr(C)]
+struct UinputSetup {
+    id: InputId,
+    name: [u8; UINPUT_MAX_NAME_SIZE],
+    ff_effects_max: u32,
+}
+
+#[repr(C)]
+struct UinputAbsSetup {
+    code: u16,
+    absinfo: AbsInfo,
+}
+
+#[repr(C)]
+struct UinputUserDev {
+    name: [u8; UINPUT_MAX_NAME_SIZE],
+    id: InputId,
+    ff_effects_max: u32,
+    absmax: [i32; ABS_CNT],
+    absmin: [i32; ABS_CNT],
+    absfuzz: [i32; ABS_CNT],
+    absflat: [i32; ABS_CNT],
+}
+
+impl InputEvent {
+    /// Create an event stamped with the current time.
+    pub fn new(kind: u16, code: u16, value: i32) -> InputEvent {
+        InputEvent::new_at(kind, code, value, SystemTime::now())
+    }
+    /// Create an event with an explicit timestamp.
+    pub fn new_at(kind: u16, code: u16, value: i32, time: SystemTime) -> InputEvent {
+        let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+        InputEvent {
+            time: timeval {
+                tv_sec: since_epoch.as_secs() as libc::time_t,
+                tv_usec: since_epoch.subsec_micros() as libc::suseconds_t,
+            },
+            kind,
+            code,
+            value,
+        }
+    }
+    /// The timestamp of the event.
+    pub fn system_time(&self) -> SystemTime {
+        UNIX_EPOCH + Duration::new(self.time.tv_sec as u64, self.time.tv_usec as u32 * 1000)
+    }
+}
+
+/// Builder of a uinput virtual device, with full control over its capabilities.
+#[derive(Clone, Debug)]
+pub struct UInputBuilder {
+    path: Option<PathBuf>,
+    name: String,
+    phys: Option<String>,
+    id: InputId,
+    bits: Vec<(u16, u16)>,
+    properties: Vec<u16>,
+    abs: Vec<(u16, AbsInfo)>,
+    legacy: bool,
+}
+
+impl UInputBuilder {
+    /// Create a builder for a device named `name` on the virtual bus.
+    pub fn new(name: &str) -> UInputBuilder {
+        UInputBuilder {
+            path: None,
+            name: name.to_string(),
+            phys: None,
+            id: InputId {
+                bustype: BUS_VIRTUAL,
+                vendor: 0,
+                product: 0,
+                version: 1,
+            },
+            bits: vec![],
+            properties: vec![],
+            abs: vec![],
+            legacy: false,
+        }
+    }
+    /// Use another uinput node than the detected one.
+    pub fn path<P: AsRef<Path>>(mut self, path: P) -> UInputBuilder {
+        self.path = Some(path.as_ref().to_path_buf());
+        self
+    }
+    pub fn id(mut self, id: InputId) -> UInputBuilder {
+        self.id = id;
+        self
+    }
+    pub fn phys(mut self, phys: &str) -> UInputBuilder {
+        self.phys = Some(phys.to_string());
+        self
+    }
+    /// Enable the event type `kind` without any code.
+    pub fn event_type(mut self, kind: u16) -> UInputBuilder {
+        self.bits.push((kind, u16::MAX));
+        self
+    }
+    /// Enable the code `code` of the event type `kind`.
+    pub fn event(mut self, kind: u16, code: u16) -> UInputBuilder {
+        self.bits.push((kind, code));
+        self
+    }
+    pub fn key(self, code: u16) -> UInputBuilder {
+        self.event(EV_KEY, code)
+    }
+    pub fn keys(mut self, codes: &[u16]) -> UInputBuilder {
+        for code in codes {
+            self = self.key(*code);
+        }
+        self
+    }
+    /// Disable the keys enabled before.
+    pub fn without_keys(mut self, codes: &[u8]) -> UInputBuilder {
+        self.bits.retain(|&(kind, code)| {
+            kind != EV_KEY || code > 0xff || !codes.contains(&(code as u8))
+        });
+        self
+    }
+    pub fn rel(self, code: u16) -> UInputBuilder {
+        self.event(EV_REL, code)
+    }
+    /// Enable the absolute axis `code` with the given range.
+    pub fn abs(mut self, code: u16, info: AbsInfo) -> UInputBuilder {
+        self.abs.push((code, info));
+        self.event(EV_ABS, code)
+    }
+    pub fn msc(self, code: u16) -> UInputBuilder {
+        self.event(EV_MSC, code)
+    }
+    pub fn led(self, code: u16) -> UInputBuilder {
+        self.event(EV_LED, code)
+    }
+    pub fn switch(self, code: u16) -> UInputBuilder {
+        self.event(EV_SW, code)
+    }
+    /// Enable the kernel autorepeat (`EV_REP`).
+    pub fn repeat(self) -> UInputBuilder {
+        self.event_type(EV_REP)
+    }
+    pub fn property(mut self, property: u16) -> UInputBuilder {
+        self.properties.push(property);
+        self
+    }
+    /// Force the legacy `uinput_user_dev` setup even if `UI_DEV_SETUP` is available.
+    pub fn legacy(mut self, legacy: bool) -> UInputBuilder {
+        self.legacy = legacy;
+        self
+    }
+
+    /// Description of the device in an evemu file.
+    pub fn evemu_device(&self) -> EvemuDevice {
+        let mut event_types: Vec<u16> = self.bits.iter().map(|&(kind, _)| kind).collect();
+        event_types.push(EV_SYN);
+        event_types.sort();
+        event_types.dedup();
+        let mut codes: Vec<(u16, u16)> = self
+            .bits
+            .iter()
+            .filter(|&&(_, code)| code != u16::MAX)
+            .cloned()
+            .collect();
+        codes.sort();
+        codes.dedup();
+        EvemuDevice {
+            name: self.name.clone(),
+            id: self.id,
+            properties: self.properties.clone(),
+            event_types,
+            codes,
+            abs: self.abs.clone(),
+        }
+    }
+
+    /// Open the uinput node, register the capabilities and create the device.
+    ///
+    /// Returns once the event node of the device exists, waiting at most one second.
+    pub fn create(&self) -> Result<UInputDevice, String> {
+        let path = match self.path {
+            Some(ref path) => path.clone(),
+            None => get_path_uinput()?.to_path_buf(),
+        };
+        let file = open_uinput(&path)?;
+        let fd = file.as_raw_fd();
+        let path_str = path.to_str().unwrap_or("PATH_ERROR");
+
+        let mut types: Vec<u16> = self.bits.iter().map(|&(kind, _)| kind).collect();
+        types.sort();
+        types.dedup();
+        for kind in types {
+            ioctl_int(fd, UI_SET_EVBIT, kind as c_int, path_str)?;
+        }
+        for &(kind, code) in self.bits.iter() {
+            let request = match kind {
+                EV_KEY => UI_SET_KEYBIT,
+                EV_REL => UI_SET_RELBIT,
+                EV_ABS => UI_SET_ABSBIT,
+                EV_MSC => UI_SET_MSCBIT,
+                EV_LED => UI_SET_LEDBIT,
+                EV_SND => UI_SET_SNDBIT,
+                EV_SW => UI_SET_SWBIT,
+                _ => continue,
+            };
+            if code != u16::MAX {
+                ioctl_int(fd, request, code as c_int, path_str)?;
+            }
+        }
+        for property in self.properties.iter() {
+            ioctl_int(fd, UI_SET_PROPBIT, *property as c_int, path_str)?;
+        }
+        if let Some(ref phys) = self.phys {
+            let phys = match CString::new(phys.as_str()) {
+                Ok(phys) => phys,
+                Err(error) => return Err(error.to_string()),
+            };
+            if unsafe { libc::ioctl(fd, UI_SET_PHYS as _, phys.as_ptr()) } < 0 {
+                return Err(last_error(path_str));
+            }
+        }
+
+        let version = uinput_version(fd);
+        let mut file = file;
+        if !self.legacy && version >= UINPUT_VERSION_SETUP {
+            self.setup(fd, path_str)?;
+        } else {
+            let user_dev = self.user_dev()?;
+            let bytes = unsafe {
+                slice::from_raw_parts(
+                    &user_dev as *const _ as *const u8,
+                    mem::size_of::<UinputUserDev>(),
+                )
+            };
+            if let Err(error) = file.write_all(bytes) {
+                return Err(format!("{}: {}", path_str, error));
+            }
+        }
+        if unsafe { libc::ioctl(fd, UI_DEV_CREATE as _) } < 0 {
+            return Err(last_error(path_str));
+        }
+        let sysname = if version >= UINPUT_VERSION_SETUP {
+            get_sysname(fd)
+        } else {
+            None
+        };
+        let mut device = UInputDevice::from_file(file);
+        device.created = true;
+        device.sysname = sysname;
+        device.wait_ready(READY_TIMEOUT);
+        Ok(device)
+    }
+
+    fn name_bytes(&self) -> Result<[u8; UINPUT_MAX_NAME_SIZE], String> {
+        let bytes = self.name.as_bytes();
+        if bytes.len() >= UINPUT_MAX_NAME_SIZE || bytes.contains(&0) {
+            return Err(format!("invalid uinput device name {:?}", self.name));
+        }
+        let mut name = [0u8; UINPUT_MAX_NAME_SIZE];
+        name[..bytes.len()].copy_from_slice(bytes);
+        Ok(name)
+    }
+
+    fn setup(&self, fd: c_int, path: &str) -> Result<(), String> {
+        let setup = UinputSetup {
+            id: self.id,
+            name: self.name_bytes()?,
+            ff_effects_max: 0,
+        };
+        if unsafe { libc::ioctl(fd, UI_DEV_SETUP as _, &setup) } < 0 {
+            return Err(last_error(path));
+        }
+        for &(code, absinfo) in self.abs.iter() {
+            let abs_setup = UinputAbsSetup { code, absinfo };
+            if unsafe { libc::ioctl(fd, UI_ABS_SETUP as _, &abs_setup) } < 0 {
+                return Err(last_error(path));
+            }
+        }
+        Ok(())
+    }
+
+    fn user_dev(&self) -> Result<UinputUserDev, String> {
+        let mut user_dev = UinputUserDev {
+            name: self.name_bytes()?,
+            id: self.id,
+            ff_effects_max: 0,
+            absmax: [0; ABS_CNT],
+            absmin: [0; ABS_CNT],
+            absfuzz: [0; ABS_CNT],
+            absflat: [0; ABS_CNT],
+        };
+        for &(code, info) in self.abs.iter() {
+            let code = code as usize;
+            if code >= ABS_CNT {
+                return Err(format!("invalid absolute axis {}", code));
+            }
+            user_dev.absmax[code] = info.maximum;
+            user_dev.absmin[code] = info.minimum;
+            user_dev.absfuzz[code] = info.fuzz;
+            user_dev.absflat[code] = info.flat;
+        }
+        Ok(user_dev)
+    }
+}
+
+/// A created uinput virtual device, destroyed on drop.
+///
+/// Events are buffered until `flush`, which writes every complete sync frame in one `write`.
+pub struct UInputDevice {
+    file: File,
+    created: bool,
+    sysname: Option<String>,
+    buffer: Vec<InputEvent>,
+    /// Number of buffered events ending with a `SYN_REPORT`.
+    complete: usize,
+    max_events_per_second: Option<u32>,
+    next_write: Option<Instant>,
+    /// Evemu file receiving the written events, with the time of its first event.
+    evemu: Option<(File, Option<SystemTime>)>,
+}
+
+impl UInputDevice {
+    /// Wrap a uinput device file created elsewhere, it is not destroyed on drop.
+    pub fn from_file(file: File) -> UInputDevice {
+        UInputDevice {
+            file,
+            created: false,
+            sysname: None,
+            buffer: vec![],
+            complete: 0,
+            max_events_per_second: None,
+            next_write: None,
+            evemu: None,
+        }
+    }
+    /// Name of the device in `/sys/devices/virtual/input`, when the kernel reports it.
+    pub fn sysname(&self) -> Option<&str> {
+        self.sysname.as_deref()
+    }
+    /// Wait until the event node of the device exists, at most `timeout`.
+    ///
+    /// Without the sysname of the device, this waits the whole `timeout`.
+    pub fn wait_ready(&self, timeout: Duration) -> bool {
+        let start = Instant::now();
+        let sysfs = match self.sysname {
+            Some(ref sysname) => Path::new("/sys/devices/virtual/input").join(sysname),
+            None => {
+                sleep(timeout);
+                return false;
+            }
+        };
+        while start.elapsed() < timeout {
+            let ready = fs::read_dir(&sysfs)
+                .map(|entries| {
+                    entries.filter_map(|entry| entry.ok()).any(|entry| {
+                        let name = entry.file_name();
+                        let name = name.to_string_lossy();
+                        name.starts_with("event") && Path::new("/dev/input").join(&*name).exists()
+                    })
+                })
+                .unwrap_or(false);
+            if ready {
+                return true;
+            }
+            sleep(Duration::from_millis(10));
+        }
+        false
+    }
+    /// Limit the number of events written per second, `None` for no limit.
+    ///
+    /// Sync frames are never split, a frame is delayed until the whole frame fits in the limit.
+    pub fn set_max_events_per_second(&mut self, max: Option<u32>) {
+        self.max_events_per_second = max.filter(|max| *max > 0);
+        self.next_write = None;
+    }
+    /// Create the evemu file `path` describing the device as `description`, then write there
+    /// every event written to the device.
+    pub fn export_evemu<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+        description: &EvemuDevice,
+    ) -> Result<(), String> {
+        let path = path.as_ref();
+        let path_str = path.to_str().unwrap_or("PATH_ERROR");
+        let mut file = match File::create(path) {
+            Ok(file) => file,
+            Err(error) => return Err(format!("{}: {}", path_str, error)),
+        };
+        let evemu = Evemu {
+            device: Some(description.clone()),
+            events: vec![],
+        };
+        if let Err(error) = file.write_all(evemu.to_text().as_bytes()) {
+            return Err(format!("{}: {}", path_str, error));
+        }
+        self.evemu = Some((file, None));
+        Ok(())
+    }
+    /// Buffer an event stamped with the current time.
+    pub fn write_event(&mut self, kind: u16, code: u16, value: i32) -> Result<(), String> {
+        self.write_input_event(&InputEvent::new(kind, code, value))
+    }
+    /// Buffer an event with its own timestamp.
+    pub fn write_input_event(&mut self, event: &InputEvent) -> Result<(), String> {
+        self.buffer.push(*event);
+        if event.kind == EV_SYN && event.code == SYN_REPORT {
+            self.complete = self.buffer.len();
+        }
+        Ok(())
+    }
+    /// End the sync frame with a `SYN_REPORT` event.
+    pub fn synchronize(&mut self) -> Result<(), String> {
+        self.write_event(EV_SYN, SYN_REPORT, 0)
+    }
+    /// Write the buffered sync frames to the device.
+    pub fn flush(&mut self) -> Result<(), String> {
+        let frames: Vec<InputEvent> = self.buffer.drain(..self.complete).collect();
+        self.complete = 0;
+        let max = match self.max_events_per_second {
+            None => return self.write_all(&frames),
+            Some(max) => max,
+        };
+        let mut start = 0;
+        for (index, event) in frames.iter().enumerate() {
+            if event.kind != EV_SYN || event.code != SYN_REPORT {
+                continue;
+            }
+            let frame = &frames[start..index + 1];
+            start = index + 1;
+            if let Some(next_write) = self.next_write {
+                let now = Instant::now();
+                if next_write > now {
+                    sleep(next_write - now);
+                }
+            }
+            self.write_all(frame)?;
+            let interval = Duration::from_secs(frame.len() as u64) / max;
+            self.next_write = Some(Instant::now() + interval);
+        }
+        Ok(())
+    }
+    /// Read the events sent by the kernel to the device, such as `EV_LED`, without blocking.
+    pub fn read_events(&mut self) -> Result<Vec<InputEvent>, String> {
+        read_input_events(&mut self.file)
+    }
+    fn write_all(&mut self, events: &[InputEvent]) -> Result<(), String> {
+        if events.is_empty() {
+            return Ok(());
+        }
+        let bytes = unsafe {
+            slice::from_raw_parts(events.as_ptr() as *const u8, mem::size_of_val(events))
+        };
+        if let Err(error) = self.file.write_all(bytes) {
+            return Err(error.to_string());
+        }
+        if let Some((ref mut file, ref mut start)) = self.evemu {
+            let start = *start.get_or_insert(events[0].system_time());
+            let lines: String = events
+                .iter()
+                .map(|event| {
+                    EvemuEvent {
+                        time: event
+                            .system_time()
+                            .duration_since(start)
+                            .unwrap_or_default(),
+                        kind: event.kind,
+                        code: event.code,
+                        value: event.value,
+                    }
+                    .to_line()
+                })
+                .collect();
+            if let Err(error) = file.write_all(lines.as_bytes()) {
+                return Err(error.to_string());
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Drop for UInputDevice {
+    fn drop(&mut self) {
+        self.flush().ok();
+        if self.created {
+            unsafe {
+                libc::ioctl(self.file.as_raw_fd(), UI_DEV_DESTROY as _);
+            }
+        }
+    }
+}
+
+/// Options of the Linux virtual keyboard.
+#[derive(Clone, Debug, Default)]
+pub struct LinuxKeyBDConfig {
+    /// Keys advertised by the device besides the modifiers, `None` for every key.
+    pub keys: Option<Vec<KeyboardKey>>,
+    /// Limit of events written per second, for consumers dropping keys when flooded.
+    pub max_events_per_second: Option<u32>,
+    /// Advertise `EV_REP` and let the kernel repeat held keys with this timing.
+    ///
+    /// Without it, `hold_key` emulates the repeats with the default timing.
+    pub repeat: Option<KeyRepeat>,
+    /// What to do with the modifiers held on the physical keyboards when launching.
+    pub held_modifiers: HeldModifiers,
+    /// Send the media and system keys from a companion "consumer control" device.
+    pub consumer_control: bool,
+    /// Write the device and every event it sends to this evemu file, for `evemu-play`.
+    ///
+    /// An evemu file describes one device, the export fails with `consumer_control`.
+    pub evemu_export: Option<PathBuf>,
+}
+
+/// Handling of the modifiers held on the physical keyboards, read with `EVIOCGKEY`.
+///
+/// They can not be released from the virtual device, the kernel drops the release of a key the
+/// device did not press.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub enum HeldModifiers {
+    /// Keys are sent without looking at the physical keyboards.
+    #[default]
+    Ignore,
+    /// Launching a `KeyBonding` fails while other modifiers are held.
+    Refuse,
+}
+
+/// Autorepeat timing of held keys.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct KeyRepeat {
+    /// Time before the first repeat.
+    pub delay: Duration,
+    /// Time between two repeats.
+    pub period: Duration,
+}
+
+impl Default for KeyRepeat {
+    /// The kernel default, 250ms delay and 33ms period.
+    fn default() -> KeyRepeat {
+        KeyRepeat {
+            delay: Duration::from_millis(250),
+            period: Duration::from_millis(33),
+        }
+    }
+}
 
 pub struct LinuxKeyBD {
-    instance: Device,
+    instance: UInputDevice,
+    /// Key codes advertised by the device, `None` when all keys are advertised.
+    keys: Option<Vec<u8>>,
+    repeat: KeyRepeat,
+    /// The kernel repeats held keys itself (`EV_REP`).
+    kernel_repeat: bool,
+    /// Lock LEDs reported by the kernel, a bit by `LED_*` code.
+    leds: u8,
+    held_modifiers: HeldModifiers,
+    /// Companion device of the media and system keys.
+    consumer: Option<UInputDevice>,
 }
 
+/// Time waiting the event node of a created device.
+const READY_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Time waiting the kernel to report the LED of a toggled lock.
+const LED_TIMEOUT: Duration = Duration::from_millis(100);
+
 impl KBPlatform for LinuxKeyBD {
-    fn run_action(&mut self, key_bonding: KeyBonding) {
+    fn run_action(&mut self, key_bonding: KeyBonding) -> Result<(), String> {
+        // Nothing is written for a rejected KeyBonding, no key stays held.
+        for value in key_bonding.keys.iter() {
+            self.check_key(*value as u8)?;
+        }
+        if self.held_modifiers == HeldModifiers::Refuse {
+            let conflicting = conflicting_modifiers(&evdev::held_keys()?, &key_bonding);
+            if !conflicting.is_empty() {
+                return Err(format!(
+                    "modifiers {:?} are held on the physical keyboard",
+                    conflicting
+                ));
+            }
+        }
         if key_bonding.has_alt {
-            self.down_key(K_ALT);
+            self.down_key(K_ALT)?;
         }
         if key_bonding.has_altgr {
-            self.down_key(K_RIGHT_ALT);
+            self.down_key(K_RIGHT_ALT)?;
         }
         if key_bonding.has_shift {
-            self.down_key(K_SHIFT);
+            self.down_key(K_SHIFT)?;
         }
         if key_bonding.has_ctrl {
-            self.down_key(K_CTRL);
+            self.down_key(K_CTRL)?;
         }
         if key_bonding.has_rshift {
-            self.down_key(K_RIGHT_SHIFT);
+            self.down_key(K_RIGHT_SHIFT)?;
         }
         if key_bonding.has_rctrl {
-            self.down_key(K_RIGHT_CTRL);
+            self.down_key(K_RIGHT_CTRL)?;
+        }
+        for value in key_bonding.keys.iter() {
+            self.down_key(*value as u8)?;
+        }
+        for value in key_bonding.keys.iter() {
+            self.up_key(*value as u8)?;
         }
-        key_bonding.keys.iter().for_each(|value| {
-            self.down_key(*value as u8);
-        });
-        key_bonding.keys.iter().for_each(|value| {
-            self.up_key(*value as u8);
-        });
         if key_bonding.has_alt {
-            self.up_key(K_ALT);
+            self.up_key(K_ALT)?;
         }
         if key_bonding.has_altgr {
-            self.up_key(K_RIGHT_ALT);
+            self.up_key(K_RIGHT_ALT)?;
         }
         if key_bonding.has_shift {
-            self.up_key(K_SHIFT);
+            self.up_key(K_SHIFT)?;
         }
         if key_bonding.has_ctrl {
-            self.up_key(K_CTRL);
+            self.up_key(K_CTRL)?;
         }
         if key_bonding.has_rshift {
-            self.up_key(K_RIGHT_SHIFT);
+            self.up_key(K_RIGHT_SHIFT)?;
         }
         if key_bonding.has_rctrl {
-            self.up_key(K_RIGHT_CTRL);
+            self.up_key(K_RIGHT_CTRL)?;
         }
-        self.instance.synchronize().unwrap();
+        self.flush()
+    }
+
+    fn hold_key(&mut self, key: KeyboardKey, duration: Duration) -> Result<(), String> {
+        let key = key as u8;
+        self.check_key(key)?;
+        let watch = Watch::start();
+        let start = Instant::now();
+        self.down_key(key)?;
+        self.flush()?;
+        // The key is released even when the emergency stop ends the hold.
+        let held = self.repeat_key(watch, key, start, duration);
+        self.up_key(key)?;
+        self.flush()?;
+        held
+    }
+
+    fn has_key_transitions(&self) -> bool {
+        true
+    }
+
+    fn key_transition(&mut self, code: u16, pressed: bool) -> Result<(), String> {
+        if code > u8::MAX as u16 {
+            return Err(format!("key code {} is not advertised by the device", code));
+        }
+        self.write_key(code as u8, pressed as i32)?;
+        self.flush()
+    }
+
+    fn lock_state(&mut self, lock: LockKey) -> Result<bool, String> {
+        self.update_leds()?;
+        Ok(self.leds & (1 << lock_led(lock)) != 0)
+    }
+
+    fn set_lock_state(&mut self, lock: LockKey, on: bool) -> Result<(), String> {
+        if self.lock_state(lock)? == on {
+            return Ok(());
+        }
+        let key = match lock {
+            LockKey::CapsLock => KeyboardKey::KeyCAPSLock,
+            LockKey::NumLock => KeyboardKey::KeyNUMLock,
+            LockKey::ScrollLock => KeyboardKey::KeyScrollLock,
+        } as u8;
+        self.check_key(key)?;
+        self.down_key(key)?;
+        self.up_key(key)?;
+        self.flush()?;
+        let bit = 1 << lock_led(lock);
+        let start = Instant::now();
+        while start.elapsed() < LED_TIMEOUT {
+            sleep(Duration::from_millis(5));
+            self.update_leds()?;
+            if (self.leds & bit != 0) == on {
+                return Ok(());
+            }
+        }
+        // Nobody reported the LED, keep the state we asked for.
+        if on {
+            self.leds |= bit;
+        } else {
+            self.leds &= !bit;
+        }
+        Ok(())
     }
 }
 
 impl LinuxKeyBD {
-    pub fn new() -> Result<Box<KBPlatform>, String> {
-        let path_uinput = match get_path_uinput() {
-            Ok(path) => path,
-            Err(error) => return Err(error.to_string()),
+    /// Create a device advertising every keyboard key.
+    #[allow(clippy::new_ret_no_self)]
+    pub fn new() -> Result<Box<dyn KBPlatform>, String> {
+        LinuxKeyBD::new_with_config(LinuxKeyBDConfig::default())
+    }
+    /// Create a device advertising only the given keys and the modifiers.
+    ///
+    /// Running a `KeyBonding` with a key outside this set returns an error.
+    pub fn new_with_keys(keys: &[KeyboardKey]) -> Result<Box<dyn KBPlatform>, String> {
+        LinuxKeyBD::new_with_config(LinuxKeyBDConfig {
+            keys: Some(keys.to_vec()),
+            ..LinuxKeyBDConfig::default()
+        })
+    }
+    /// Create a device with the given options.
+    pub fn new_with_config(config: LinuxKeyBDConfig) -> Result<Box<dyn KBPlatform>, String> {
+        Ok(Box::new(LinuxKeyBD::create(config)?))
+    }
+    pub(crate) fn create(config: LinuxKeyBDConfig) -> Result<LinuxKeyBD, String> {
+        if config.consumer_control && config.evemu_export.is_some() {
+            return Err(
+                "the events of the consumer control device can not be exported to evemu"
+                    .to_string(),
+            );
+        }
+        let keys = config.keys.map(|keys| {
+            let mut codes: Vec<u8> = keys.iter().map(|key| *key as u8).collect();
+            codes.extend_from_slice(&MODIFIERS);
+            codes.sort();
+            codes.dedup();
+            codes
+        });
+        let builder = keyboard_builder(keys.as_ref(), config.repeat.is_some());
+        let consumer_control = config.consumer_control;
+        let builder = if consumer_control {
+            builder.without_keys(&consumer_codes())
+        } else {
+            builder
         };
-        match File::open(path_uinput) {
-            Ok(file) => file,
-            Err(error) => {
-                if error.kind() == ErrorKind::PermissionDenied {
-                    let path = path_uinput.to_str().unwrap_or("PATH_ERROR");
-                    return Err(format!(
-                        "permission error for {} try cmd : sudo chmod +0666 {}",
-                        path, path
-                    ));
-                }
-                return Err(error.to_string());
+        let consumer = if consumer_control {
+            let mut consumer_builder =
+                UInputBuilder::new(&format!("{} consumer control", DEVICE_NAME)).msc(MSC_SCAN);
+            if config.repeat.is_some() {
+                consumer_builder = consumer_builder.repeat();
             }
+            let mut device = consumer_codes()
+                .iter()
+                .filter(|code| keys.as_ref().is_none_or(|keys| keys.contains(code)))
+                .fold(consumer_builder, |builder, code| builder.key(*code as u16))
+                .create()?;
+            configure(&mut device, config.max_events_per_second, config.repeat)?;
+            Some(device)
+        } else {
+            None
         };
-        let mut builder = match uinput::open(path_uinput) {
-            Ok(builder) => builder,
-            Err(error) => return Err(error.to_string()),
-        };
-        builder = match builder.name("keybd_event") {
-            Ok(builder) => builder,
-            Err(error) => return Err(error.to_string()),
-        };
-        builder = match builder.event(uinput::event::Keyboard::All) {
-            Ok(builder) => builder,
+        let mut device = builder.create()?;
+        configure(&mut device, config.max_events_per_second, config.repeat)?;
+        if let Some(ref path) = config.evemu_export {
+            device.export_evemu(path, &builder.evemu_device())?;
+        }
+        let mut keyboard = LinuxKeyBD::with_device(device, keys, config.repeat);
+        keyboard.held_modifiers = config.held_modifiers;
+        keyboard.consumer = consumer;
+        Ok(keyboard)
+    }
+    fn with_device(
+        instance: UInputDevice,
+        keys: Option<Vec<u8>>,
+        repeat: Option<KeyRepeat>,
+    ) -> LinuxKeyBD {
+        LinuxKeyBD {
+            instance,
+            keys,
+            repeat: repeat.unwrap_or_default(),
+            kernel_repeat: repeat.is_some(),
+            leds: 0,
+            held_modifiers: HeldModifiers::Ignore,
+            consumer: None,
+        }
+    }
+    /// Wait until `start + duration` with the key held, repeating it if the kernel does not.
+    fn repeat_key(
+        &mut self,
+        watch: Watch,
+        key: u8,
+        start: Instant,
+        duration: Duration,
+    ) -> Result<(), String> {
+        if !self.kernel_repeat {
+            let mut next_repeat = start + self.repeat.delay;
+            while next_repeat < start + duration {
+                watch.sleep(next_repeat.saturating_duration_since(Instant::now()))?;
+                let device = self.device_for(key);
+                device.write_event(EV_KEY, key as u16, 2)?;
+                device.synchronize()?;
+                device.flush()?;
+                next_repeat += self.repeat.period;
+            }
+        }
+        watch.sleep((start + duration).saturating_duration_since(Instant::now()))
+    }
+    /// Device sending the key, the companion device for media and system keys.
+    fn device_for(&mut self, key: u8) -> &mut UInputDevice {
+        match self.consumer {
+            Some(ref mut consumer) if consumer_codes().contains(&key) => consumer,
+            _ => &mut self.instance,
+        }
+    }
+    pub(crate) fn flush(&mut self) -> Result<(), String> {
+        if let Some(ref mut consumer) = self.consumer {
+            consumer.flush()?;
+        }
+        self.instance.flush()
+    }
+    fn update_leds(&mut self) -> Result<(), String> {
+        for event in self.instance.read_events()? {
+            if event.kind == EV_LED && event.code < 8 {
+                if event.value != 0 {
+                    self.leds |= 1 << event.code;
+                } else {
+                    self.leds &= !(1 << event.code);
+                }
+            }
+        }
+        Ok(())
+    }
+    fn down_key(&mut self, key: u8) -> Result<(), String> {
+        self.write_key(key, 1)
+    }
+    fn up_key(&mut self, key: u8) -> Result<(), String> {
+        self.write_key(key, 0)
+    }
+    fn check_key(&self, key: u8) -> Result<(), String> {
+        if let Some(ref codes) = self.keys {
+            if codes.binary_search(&key).is_err() {
+                return Err(format!("key code {} is not advertised by the device", key));
+            }
+        }
+        Ok(())
+    }
+    fn write_key(&mut self, key: u8, value: i32) -> Result<(), String> {
+        self.check_key(key)?;
+        self.write_transition(key, value)
+    }
+    pub(crate) fn write_transition(&mut self, key: u8, value: i32) -> Result<(), String> {
+        // Each transition is its own frame, as a physical keyboard. There is no hardware
+        // scancode, the key code is reported as scancode.
+        let device = self.device_for(key);
+        device.write_event(EV_MSC, MSC_SCAN, key as i32)?;
+        device.write_event(EV_KEY, key as u16, value)?;
+        device.synchronize()
+    }
+}
+
+fn consumer_codes() -> Vec<u8> {
+    CONSUMER_CONTROL_KEYS.iter().map(|key| *key as u8).collect()
+}
+
+/// Builder of the virtual keyboard advertising `keys`, or every key.
+pub(crate) fn keyboard_builder(keys: Option<&Vec<u8>>, repeat: bool) -> UInputBuilder {
+    let mut builder = UInputBuilder::new(DEVICE_NAME)
+        .msc(MSC_SCAN)
+        .led(LED_NUML)
+        .led(LED_CAPSL)
+        .led(LED_SCROLLL);
+    if repeat {
+        builder = builder.repeat();
+    }
+    match keys {
+        // Every key below the first button range, as a physical keyboard.
+        None => (1..0x100).fold(builder, |builder, code| builder.key(code)),
+        Some(codes) => codes
+            .iter()
+            .fold(builder, |builder, code| builder.key(*code as u16)),
+    }
+}
+
+/// Throttle of a created keyboard device and the timing of its `EV_REP`.
+fn configure(
+    device: &mut UInputDevice,
+    max_events_per_second: Option<u32>,
+    repeat: Option<KeyRepeat>,
+) -> Result<(), String> {
+    device.set_max_events_per_second(max_events_per_second);
+    if let Some(repeat) = repeat {
+        device.write_event(EV_REP, REP_DELAY, repeat.delay.as_millis() as i32)?;
+        device.write_event(EV_REP, REP_PERIOD, repeat.period.as_millis() as i32)?;
+        device.synchronize()?;
+        device.flush()?;
+    }
+    Ok(())
+}
+
+/// Modifier keys of the `KeyBonding`, in the order they are pressed.
+pub(crate) fn bonding_modifiers(key_bonding: &KeyBonding) -> Vec<u16> {
+    [
+        (K_ALT, key_bonding.has_alt),
+        (K_RIGHT_ALT, key_bonding.has_altgr),
+        (K_SHIFT, key_bonding.has_shift),
+        (K_CTRL, key_bonding.has_ctrl),
+        (K_RIGHT_SHIFT, key_bonding.has_rshift),
+        (K_RIGHT_CTRL, key_bonding.has_rctrl),
+    ]
+    .iter()
+    .filter(|&&(_, used)| used)
+    .map(|&(key, _)| key as u16)
+    .collect()
+}
+
+/// Modifiers held on the physical keyboards which are not part of the `KeyBonding`.
+fn conflicting_modifiers(held: &[u16], key_bonding: &KeyBonding) -> Vec<u8> {
+    let wanted = [
+        (K_CTRL, key_bonding.has_ctrl),
+        (K_RIGHT_CTRL, key_bonding.has_rctrl),
+        (K_SHIFT, key_bonding.has_shift),
+        (K_RIGHT_SHIFT, key_bonding.has_rshift),
+        (K_ALT, key_bonding.has_alt),
+        (K_RIGHT_ALT, key_bonding.has_altgr),
+        (K_LEFT_META, false),
+        (K_RIGHT_META, false),
+    ];
+    wanted
+        .iter()
+        .filter(|&&(key, used)| !used && held.contains(&(key as u16)))
+        .map(|&(key, _)| key)
+        .collect()
+}
+
+fn lock_led(lock: LockKey) -> u16 {
+    match lock {
+        LockKey::CapsLock => LED_CAPSL,
+        LockKey::NumLock => LED_NUML,
+        LockKey::ScrollLock => LED_SCROLLL,
+    }
+}
+
+fn open_uinput(path: &Path) -> Result<File, String> {
+    match OpenOptions::new()
+        .read(true)
+        .write(true)
+        .custom_flags(libc::O_NONBLOCK)
+        .open(path)
+    {
+        Ok(file) => Ok(file),
+        Err(error) => {
+            if error.kind() == ErrorKind::PermissionDenied {
+                let path = path.to_str().unwrap_or("PATH_ERROR");
+                return Err(format!(
+                    "permission error for {} try cmd : sudo chmod +0666 {}",
+                    path, path
+                ));
+            }
+            Err(error.to_string())
+        }
+    }
+}
+
+/// Read the pending events of a non-blocking input file.
+pub(crate) fn read_input_events(file: &mut File) -> Result<Vec<InputEvent>, String> {
+    let mut events = vec![];
+    let mut buffer = [0u8; 64 * mem::size_of::<InputEvent>()];
+    loop {
+        let size = match file.read(&mut buffer) {
+            Ok(0) => return Ok(events),
+            Ok(size) => size,
+            Err(ref error) if error.kind() == ErrorKind::WouldBlock => return Ok(events),
             Err(error) => return Err(error.to_string()),
         };
-        return match builder.create() {
-            Ok(device) => Ok(Box::new(LinuxKeyBD { instance: device })),
-            Err(error) => Err(error.to_string()),
-        };
+        for chunk in buffer[..size].chunks_exact(mem::size_of::<InputEvent>()) {
+            events.push(unsafe { std::ptr::read_unaligned(chunk.as_ptr() as *const InputEvent) });
+        }
     }
-    fn down_key(&mut self, key: u8) -> bool {
-        return self.instance.write(EV_KEY, key as i32, 1).is_ok();
+}
+
+fn ioctl_int(fd: c_int, request: c_ulong, value: c_int, path: &str) -> Result<(), String> {
+    if unsafe { libc::ioctl(fd, request as _, value) } < 0 {
+        return Err(last_error(path));
     }
-    fn up_key(&mut self, key: u8) -> bool {
-        return self.instance.write(EV_KEY, key as i32, 0).is_ok();
+    Ok(())
+}
+
+pub(crate) fn last_error(path: &str) -> String {
+    format!("{}: {}", path, std::io::Error::last_os_error())
+}
+
+fn uinput_version(fd: c_int) -> u32 {
+    let mut version: u32 = 0;
+    if unsafe { libc::ioctl(fd, UI_GET_VERSION as _, &mut version) } < 0 {
+        return 0;
+    }
+    version
+}
+
+fn get_sysname(fd: c_int) -> Option<String> {
+    let mut buffer = [0u8; 64];
+    if unsafe { libc::ioctl(fd, ui_get_sysname(buffer.len()) as _, buffer.as_mut_ptr()) } < 0 {
+        return None;
     }
+    let end = buffer.iter().position(|b| *b == 0).unwrap_or(buffer.len());
+    String::from_utf8(buffer[..end].to_vec()).ok()
 }
 
 fn get_path_uinput<'a>() -> Result<&'a Path, String> {
@@ -122,5 +1148,323 @@ fn get_path_uinput<'a>() -> Result<&'a Path, String> {
     if Path::new("/dev/input/uinput").exists() {
         return Ok(Path::new("/dev/input/uinput"));
     }
-    return Err("Not found uinput file. Try this cmd 'sudo modprobe uinput'".to_string());
+    Err("Not found uinput file. Try this cmd 'sudo modprobe uinput'".to_string())
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::fs::{self, File};
+    use std::io::Read;
+    use std::mem;
+    use std::path::PathBuf;
+    use std::time::{Duration, Instant, UNIX_EPOCH};
+
+    use super::*;
+
+    /// A regular file standing in for `/dev/uinput`.
+    pub(crate) fn stand_in(name: &str) -> PathBuf {
+        let path =
+            std::env::temp_dir().join(format!("keybd_event-{}-{}", name, std::process::id()));
+        File::create(&path).unwrap();
+        path
+    }
+
+    /// Read and remove the stand-in file, as (type, code, value, seconds, microseconds).
+    pub(crate) fn read_events(path: &PathBuf) -> Vec<(u16, u16, i32, i64, i64)> {
+        let mut bytes = vec![];
+        File::open(path).unwrap().read_to_end(&mut bytes).unwrap();
+        fs::remove_file(path).unwrap();
+        bytes
+            .chunks(mem::size_of::<InputEvent>())
+            .map(|chunk| {
+                let event: InputEvent =
+                    unsafe { std::ptr::read_unaligned(chunk.as_ptr() as *const _) };
+                (
+                    event.kind,
+                    event.code,
+                    event.value,
+                    event.time.tv_sec as i64,
+                    event.time.tv_usec as i64,
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn kernel_struct_sizes() {
+        assert_eq!(mem::size_of::<UinputSetup>(), 92);
+        assert_eq!(mem::size_of::<UinputAbsSetup>(), 28);
+        assert_eq!(mem::size_of::<UinputUserDev>(), 1116);
+        assert_eq!(UI_DEV_SETUP, 0x405c5503);
+        assert_eq!(UI_SET_KEYBIT, 0x40045565);
+    }
+
+    #[test]
+    fn write_events_with_timestamps() {
+        let path = stand_in("events");
+        {
+            let file = OpenOptions::new().write(true).open(&path).unwrap();
+            let mut device = UInputDevice::from_file(file);
+            let time = UNIX_EPOCH + Duration::new(1_600_000_000, 250_000_000);
+            device
+                .write_input_event(&InputEvent::new_at(EV_KEY, 30, 1, time))
+                .unwrap();
+            device.synchronize().unwrap();
+        }
+        let events = read_events(&path);
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0], (EV_KEY, 30, 1, 1_600_000_000, 250_000));
+        assert_eq!(
+            (events[1].0, events[1].1, events[1].2),
+            (EV_SYN, SYN_REPORT, 0)
+        );
+    }
+
+    #[test]
+    fn flush_writes_complete_frames_only() {
+        let path = stand_in("frames");
+        let file = OpenOptions::new().write(true).open(&path).unwrap();
+        let mut device = UInputDevice::from_file(file);
+        device.write_event(EV_KEY, 30, 1).unwrap();
+        device.synchronize().unwrap();
+        device.write_event(EV_KEY, 30, 0).unwrap();
+        device.flush().unwrap();
+        assert_eq!(
+            fs::metadata(&path).unwrap().len(),
+            2 * mem::size_of::<InputEvent>() as u64
+        );
+        device.synchronize().unwrap();
+        device.flush().unwrap();
+        assert_eq!(read_events(&path).len(), 4);
+    }
+
+    #[test]
+    fn throttle_spaces_frames() {
+        let path = stand_in("throttle");
+        let file = OpenOptions::new().write(true).open(&path).unwrap();
+        let mut device = UInputDevice::from_file(file);
+        device.set_max_events_per_second(Some(200));
+        let start = Instant::now();
+        for _ in 0..3 {
+            device.write_event(EV_KEY, 30, 1).unwrap();
+            device.synchronize().unwrap();
+        }
+        device.flush().unwrap();
+        // Two frames of two events wait 10ms each before the next frame.
+        assert!(start.elapsed() >= Duration::from_millis(20));
+        assert_eq!(read_events(&path).len(), 6);
+    }
+
+    #[test]
+    fn key_bonding_reports_each_transition() {
+        let path = stand_in("conformance");
+        {
+            let file = OpenOptions::new().write(true).open(&path).unwrap();
+            let mut keyboard = LinuxKeyBD::with_device(
+                UInputDevice::from_file(file),
+                Some(vec![K_CTRL, 30]),
+                None,
+            );
+            let mut key_bonding = KeyBonding {
+                has_ctrl: true,
+                has_alt: false,
+                has_shift: false,
+                has_rctrl: false,
+                has_rshift: false,
+                has_altgr: false,
+                keys: vec![KeyboardKey::KeyA],
+            };
+            keyboard.run_action(key_bonding.clone()).unwrap();
+            key_bonding.keys = vec![KeyboardKey::KeyB];
+            assert!(keyboard.run_action(key_bonding).is_err());
+        }
+        let events: Vec<(u16, u16, i32)> = read_events(&path)
+            .into_iter()
+            .map(|(kind, code, value, _, _)| (kind, code, value))
+            .collect();
+        let mut expected = vec![];
+        for &(key, value) in [(29, 1), (30, 1), (30, 0), (29, 0)].iter() {
+            expected.push((EV_MSC, MSC_SCAN, key as i32));
+            expected.push((EV_KEY, key, value));
+            expected.push((EV_SYN, SYN_REPORT, 0));
+        }
+        assert_eq!(events, expected);
+    }
+
+    #[test]
+    fn hold_key_emulates_autorepeat() {
+        let path = stand_in("repeat");
+        {
+            let file = OpenOptions::new().write(true).open(&path).unwrap();
+            let mut keyboard = LinuxKeyBD::with_device(UInputDevice::from_file(file), None, None);
+            keyboard.repeat = KeyRepeat {
+                delay: Duration::from_millis(20),
+                period: Duration::from_millis(10),
+            };
+            keyboard
+                .hold_key(KeyboardKey::KeyBACKSPACE, Duration::from_millis(55))
+                .unwrap();
+        }
+        let values: Vec<i32> = read_events(&path)
+            .into_iter()
+            .filter(|event| event.0 == EV_KEY)
+            .map(|event| event.2)
+            .collect();
+        // Repeats at 20, 30, 40 and 50ms.
+        assert_eq!(values, vec![1, 2, 2, 2, 2, 0]);
+    }
+
+    #[test]
+    fn lock_state_follows_kernel_leds() {
+        let path = stand_in("leds");
+        {
+            let mut kernel = UInputDevice::from_file(File::create(&path).unwrap());
+            kernel.write_event(EV_LED, LED_CAPSL, 1).unwrap();
+            kernel.synchronize().unwrap();
+        }
+        {
+            let file = OpenOptions::new()
+                .read(true)
+                .write(true)
+                .open(&path)
+                .unwrap();
+            let mut keyboard = LinuxKeyBD::with_device(UInputDevice::from_file(file), None, None);
+            assert!(keyboard.lock_state(LockKey::CapsLock).unwrap());
+            assert!(!keyboard.lock_state(LockKey::NumLock).unwrap());
+            keyboard.set_lock_state(LockKey::CapsLock, true).unwrap();
+            keyboard.set_lock_state(LockKey::CapsLock, false).unwrap();
+            assert!(!keyboard.lock_state(LockKey::CapsLock).unwrap());
+        }
+        let keys: Vec<(u16, i32)> = read_events(&path)
+            .into_iter()
+            .filter(|event| event.0 == EV_KEY)
+            .map(|event| (event.1, event.2))
+            .collect();
+        assert_eq!(keys, vec![(58, 1), (58, 0)]);
+    }
+
+    #[test]
+    fn conflicting_modifiers_skip_wanted_ones() {
+        let key_bonding = KeyBonding {
+            has_ctrl: false,
+            has_alt: false,
+            has_shift: true,
+            has_rctrl: false,
+            has_rshift: false,
+            has_altgr: false,
+            keys: vec![KeyboardKey::KeyA],
+        };
+        let held = [30, K_SHIFT as u16, K_RIGHT_CTRL as u16, K_LEFT_META as u16];
+        assert_eq!(
+            conflicting_modifiers(&held, &key_bonding),
+            vec![K_RIGHT_CTRL, K_LEFT_META]
+        );
+    }
+
+    #[test]
+    fn consumer_keys_use_companion_device() {
+        let path = stand_in("keyboard");
+        let consumer_path = stand_in("consumer");
+        {
+            let file = OpenOptions::new().write(true).open(&path).unwrap();
+            let mut keyboard = LinuxKeyBD::with_device(UInputDevice::from_file(file), None, None);
+            let file = OpenOptions::new().write(true).open(&consumer_path).unwrap();
+            keyboard.consumer = Some(UInputDevice::from_file(file));
+            let key_bonding = KeyBonding {
+                has_ctrl: true,
+                has_alt: false,
+                has_shift: false,
+                has_rctrl: false,
+                has_rshift: false,
+                has_altgr: false,
+                keys: vec![KeyboardKey::KeyMute],
+            };
+            keyboard.run_action(key_bonding).unwrap();
+        }
+        let keys = |path| -> Vec<(u16, i32)> {
+            read_events(path)
+                .into_iter()
+                .filter(|event| event.0 == EV_KEY)
+                .map(|event| (event.1, event.2))
+                .collect()
+        };
+        assert_eq!(keys(&path), vec![(29, 1), (29, 0)]);
+        assert_eq!(keys(&consumer_path), vec![(113, 1), (113, 0)]);
+    }
+
+    #[test]
+    fn create_on_stand_in_file_fails_cleanly() {
+        let path = stand_in("create");
+        let result = UInputBuilder::new("keybd_event")
+            .path(&path)
+            .key(30)
+            .create();
+        fs::remove_file(&path).unwrap();
+        let error = result.err().unwrap();
+        assert!(error.starts_with(path.to_str().unwrap()), "{}", error);
+    }
+
+    #[test]
+    fn legacy_user_dev_describes_device() {
+        let builder = UInputBuilder::new("keybd_event").abs(
+            0,
+            AbsInfo {
+                minimum: -10,
+                maximum: 10,
+                ..AbsInfo::default()
+            },
+        );
+        let user_dev = builder.user_dev().unwrap();
+        assert_eq!(&user_dev.name[..12], b"keybd_event\0");
+        assert_eq!(user_dev.id.bustype, BUS_VIRTUAL);
+        assert_eq!((user_dev.absmin[0], user_dev.absmax[0]), (-10, 10));
+        assert!(UInputBuilder::new(&"x".repeat(80)).user_dev().is_err());
+    }
+
+    #[test]
+    fn evemu_export_follows_written_events() {
+        let path = stand_in("evemu-device");
+        let export = stand_in("evemu-export");
+        {
+            let file = OpenOptions::new().write(true).open(&path).unwrap();
+            let mut device = UInputDevice::from_file(file);
+            let codes = vec![30u8];
+            let builder = keyboard_builder(Some(&codes), false);
+            device
+                .export_evemu(&export, &builder.evemu_device())
+                .unwrap();
+            let mut keyboard = LinuxKeyBD::with_device(device, Some(codes), None);
+            keyboard
+                .run_action(KeyBonding {
+                    keys: vec![KeyboardKey::KeyA],
+                    ..KeyBonding::default()
+                })
+                .unwrap();
+        }
+        let written = read_events(&path);
+        let text = fs::read_to_string(&export).unwrap();
+        fs::remove_file(&export).unwrap();
+        let evemu = Evemu::parse(&text).unwrap();
+        let device = evemu.device.unwrap();
+        assert_eq!(device.name, DEVICE_NAME);
+        assert!(device.codes.contains(&(EV_KEY, 30)));
+        assert!(device.codes.contains(&(EV_LED, LED_CAPSL)));
+        let exported: Vec<(u16, u16, i32)> = evemu
+            .events
+            .iter()
+            .map(|event| (event.kind, event.code, event.value))
+            .collect();
+        let written: Vec<(u16, u16, i32)> = written
+            .iter()
+            .map(|&(kind, code, value, _, _)| (kind, code, value))
+            .collect();
+        assert_eq!(exported, written);
+        assert_eq!(evemu.events[0].time, Duration::from_secs(0));
+        assert!(LinuxKeyBD::create(LinuxKeyBDConfig {
+            consumer_control: true,
+            evemu_export: Some(export),
+            ..LinuxKeyBDConfig::default()
+        })
+        .is_err());
+    }
 }
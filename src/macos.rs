@@ -14,15 +14,22 @@ pub struct MacOSKeyBD {
 }
 
 impl KBPlatform for MacOSKeyBD {
-    fn run_action(&mut self, key_bonding: KeyBonding) {
-        key_bonding.keys.iter().for_each(|keycode| {
-            self.key_press(keycode, &key_bonding).err();
-        });
+    fn run_action(&mut self, key_bonding: KeyBonding) -> Result<(), String> {
+        // Nothing is sent for a KeyBonding with an unsupported key.
+        for keycode in key_bonding.keys.iter() {
+            if MacOSKeyBD::convert_keycode(keycode).is_none() {
+                return Err(format!("{:?} is not supported on macOS", keycode));
+            }
+        }
+        for keycode in key_bonding.keys.iter() {
+            self.key_press(keycode, &key_bonding)?;
+        }
+        Ok(())
     }
 }
 
 impl MacOSKeyBD {
-    pub fn new() -> Result<Box<KBPlatform>, String> {
+    pub fn new() -> Result<Box<dyn KBPlatform>, String> {
         return Ok(Box::new(MacOSKeyBD {
             special_flags: CGEventFlags::CGEventFlagNull,
         }));
@@ -141,6 +148,7 @@ impl MacOSKeyBD {
             KeyboardKey::KeyF12 => Some(0x6F),
             KeyboardKey::KeyNUMLock => None,
             KeyboardKey::KeyScrollLock => None,
+            KeyboardKey::KeyPause => None,
             KeyboardKey::KeyRESERVED => None,
             KeyboardKey::KeyBACKSPACE => Some(0x33),
             KeyboardKey::KeyTAB => Some(0x30),
@@ -167,6 +175,25 @@ impl MacOSKeyBD {
             KeyboardKey::KeyKPEqual => Some(0x51),
             KeyboardKey::KeyKPPlusMinus => None,
             KeyboardKey::KeyKPComma => None,
+            // Media and system keys are not keyboard events on Mac OS.
+            KeyboardKey::KeyMute
+            | KeyboardKey::KeyVolumeDown
+            | KeyboardKey::KeyVolumeUp
+            | KeyboardKey::KeyPlayPause
+            | KeyboardKey::KeyStopCD
+            | KeyboardKey::KeyPreviousSong
+            | KeyboardKey::KeyNextSong
+            | KeyboardKey::KeyCalc
+            | KeyboardKey::KeyMail
+            | KeyboardKey::KeyBookmarks
+            | KeyboardKey::KeyBack
+            | KeyboardKey::KeyForward
+            | KeyboardKey::KeyHomePage
+            | KeyboardKey::KeyRefresh
+            | KeyboardKey::KeySearch
+            | KeyboardKey::KeyPower
+            | KeyboardKey::KeySleep
+            | KeyboardKey::KeyWakeUp => None,
         };
     }
 }
@@ -0,0 +1,397 @@
+//! A secondary keyboard, such as a USB numpad, used as a macro pad.
+//!
+//! The device is grabbed, its keys do nothing else than their bindings, sent from a virtual
+//! keyboard. The configuration is a TOML file, read again when it changes:
+//!
+//! ```toml
+//! [device]
+//! # A name, a vendor and product, or a path such as "/dev/input/by-id/usb-...-event-kbd".
+//! vendor = 0x04d9
+//! product = 0x1603
+//!
+//! [keys]
+//! KeyKP1 = { keys = ["KeyC"], ctrl = true }
+//! KeyKP2 = { sequence = [{ keys = ["KeyA"], ctrl = true }, { keys = ["KeyC"], ctrl = true }] }
+//! KeyKP3 = { text = "Best regards,\n" }
+//! ```
+//!
+//! The keys are named as `KeyboardKey`. Every event node matching the device is grabbed, as the
+//! keyboard and consumer control nodes of a USB numpad. The device is chosen at the opening, the
+//! changes of its section are not reloaded. Needs the feature `macropad`, and the `input` group
+//! for the grab.
+use std::collections::{BTreeMap, VecDeque};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::slice;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, SystemTime};
+
+use serde::Deserialize;
+
+use emergency::{self, Watch};
+use evdev::{self, EvdevDevice};
+use linux::{LinuxKeyBD, LinuxKeyBDConfig};
+use recorder::{select_devices, DeviceSelector, KeyTracker};
+use timeline::KeyEvent;
+use {KBPlatform, KeyBonding, KeyboardKey};
+
+const WAIT_INTERVAL: Duration = Duration::from_millis(100);
+
+/// What a key of the macro pad sends.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Binding {
+    Bonding(KeyBonding),
+    Sequence(Vec<KeyBonding>),
+    /// Text typed with a US keyboard layout.
+    Text(String),
+}
+
+impl Binding {
+    fn key_bondings(&self) -> Result<Vec<KeyBonding>, String> {
+        match *self {
+            Binding::Bonding(ref key_bonding) => Ok(vec![key_bonding.clone()]),
+            Binding::Sequence(ref key_bondings) => Ok(key_bondings.clone()),
+            Binding::Text(ref text) => KeyBonding::from_text(text),
+        }
+    }
+}
+
+/// The configuration of a macro pad.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MacroPadConfig {
+    pub device: DeviceSelector,
+    pub bindings: Vec<(KeyboardKey, Binding)>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FileConfig {
+    device: FileDevice,
+    #[serde(default)]
+    keys: BTreeMap<String, FileBinding>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FileDevice {
+    path: Option<PathBuf>,
+    name: Option<String>,
+    vendor: Option<u16>,
+    product: Option<u16>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FileBinding {
+    #[serde(flatten)]
+    bonding: FileBonding,
+    sequence: Option<Vec<FileBonding>>,
+    text: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FileBonding {
+    #[serde(default)]
+    keys: Vec<String>,
+    #[serde(default)]
+    ctrl: bool,
+    #[serde(default)]
+    shift: bool,
+    #[serde(default)]
+    alt: bool,
+    #[serde(default)]
+    rctrl: bool,
+    #[serde(default)]
+    rshift: bool,
+    #[serde(default)]
+    altgr: bool,
+}
+
+impl FileBonding {
+    fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+            && !(self.ctrl || self.shift || self.alt || self.rctrl || self.rshift || self.altgr)
+    }
+    fn key_bonding(&self) -> Result<KeyBonding, String> {
+        let mut keys = vec![];
+        for name in &self.keys {
+            keys.push(key_named(name)?);
+        }
+        Ok(KeyBonding {
+            has_ctrl: self.ctrl,
+            has_alt: self.alt,
+            has_shift: self.shift,
+            has_rctrl: self.rctrl,
+            has_rshift: self.rshift,
+            has_altgr: self.altgr,
+            keys,
+        })
+    }
+}
+
+fn key_named(name: &str) -> Result<KeyboardKey, String> {
+    match KeyEvent::from_name(name, true) {
+        Some(KeyEvent::Press(key)) => Ok(key),
+        _ => Err(format!("unknown key {}", name)),
+    }
+}
+
+impl MacroPadConfig {
+    /// Read the configuration from TOML.
+    pub fn parse(text: &str) -> Result<MacroPadConfig, String> {
+        let file: FileConfig = toml::from_str(text).map_err(|error| error.to_string())?;
+        let device = match file.device {
+            FileDevice {
+                path: Some(path),
+                name: None,
+                vendor: None,
+                product: None,
+            } => DeviceSelector::Path(path),
+            FileDevice {
+                path: None,
+                name: Some(name),
+                vendor: None,
+                product: None,
+            } => DeviceSelector::Name(name),
+            FileDevice {
+                path: None,
+                name: None,
+                vendor: Some(vendor),
+                product: Some(product),
+            } => DeviceSelector::Id { vendor, product },
+            _ => return Err("[device] needs a path, a name, or a vendor and a product".to_string()),
+        };
+        let mut bindings = vec![];
+        for (name, binding) in file.keys {
+            let key = key_named(&name)?;
+            let binding = match (binding.bonding.is_empty(), binding.sequence, binding.text) {
+                (false, None, None) => Binding::Bonding(binding.bonding.key_bonding()?),
+                (true, Some(sequence), None) => {
+                    let mut key_bondings = vec![];
+                    for bonding in &sequence {
+                        key_bondings.push(bonding.key_bonding()?);
+                    }
+                    Binding::Sequence(key_bondings)
+                }
+                (true, None, Some(text)) => {
+                    KeyBonding::from_text(&text).map_err(|error| format!("{}: {}", name, error))?;
+                    Binding::Text(text)
+                }
+                _ => {
+                    return Err(format!(
+                        "{}: needs keys, a sequence or a text, and only one of them",
+                        name
+                    ))
+                }
+            };
+            bindings.push((key, binding));
+        }
+        Ok(MacroPadConfig { device, bindings })
+    }
+    /// Read the configuration from the TOML file `path`.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<MacroPadConfig, String> {
+        let path = path.as_ref();
+        match fs::read_to_string(path) {
+            Ok(text) => MacroPadConfig::parse(&text)
+                .map_err(|error| format!("{}: {}", path.display(), error)),
+            Err(error) => Err(format!("{}: {}", path.display(), error)),
+        }
+    }
+    fn binding(&self, code: u16) -> Option<&Binding> {
+        self.bindings
+            .iter()
+            .find(|&&(key, _)| key as u16 == code)
+            .map(|(_, binding)| binding)
+    }
+}
+
+struct Source {
+    device: EvdevDevice,
+    tracker: KeyTracker,
+}
+
+/// A grabbed device sending the bindings of its keys, with its configuration reloaded on change.
+///
+/// The grab is released on drop.
+pub struct MacroPad {
+    path: PathBuf,
+    modified: Option<SystemTime>,
+    config: MacroPadConfig,
+    reload_error: Option<String>,
+    sources: Vec<Source>,
+    /// Keys pressed on the grabbed device, waiting for their bindings.
+    pending: VecDeque<u16>,
+    keyboard: LinuxKeyBD,
+}
+
+impl MacroPad {
+    /// Read the configuration file `path`, then grab the event nodes of its device.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<MacroPad, String> {
+        let path = path.as_ref().to_path_buf();
+        let modified = modified(&path);
+        let config = MacroPadConfig::load(&path)?;
+        let devices = select_devices(slice::from_ref(&config.device))?;
+        let keyboard = LinuxKeyBD::create(LinuxKeyBDConfig::default())?;
+        let mut sources = vec![];
+        for mut device in devices {
+            device.wait_keys_released()?;
+            device.grab(true)?;
+            sources.push(Source {
+                device,
+                tracker: KeyTracker::new(vec![]),
+            });
+        }
+        Ok(MacroPad {
+            path,
+            modified,
+            config,
+            reload_error: None,
+            sources,
+            pending: VecDeque::new(),
+            keyboard,
+        })
+    }
+    pub fn config(&self) -> &MacroPadConfig {
+        &self.config
+    }
+    /// Why the last change of the configuration file was refused, the previous one is kept.
+    pub fn reload_error(&self) -> Option<&str> {
+        self.reload_error.as_deref()
+    }
+    /// Read the configuration file again if it changed.
+    pub fn reload_if_changed(&mut self) {
+        let modified = modified(&self.path);
+        if modified == self.modified {
+            return;
+        }
+        self.modified = modified;
+        match MacroPadConfig::load(&self.path) {
+            Ok(config) => {
+                self.config.bindings = config.bindings;
+                self.reload_error = None;
+            }
+            Err(error) => self.reload_error = Some(error),
+        }
+    }
+    /// Read the pending events without blocking and send the bindings of the pressed keys.
+    ///
+    /// The hotkey of a `KillSwitch` pressed on the device calls the emergency stop, which abandons
+    /// the binding in progress.
+    pub fn poll(&mut self) -> Result<(), String> {
+        self.reload_if_changed();
+        self.read_pending()?;
+        while let Some(code) = self.pending.pop_front() {
+            let key_bondings = match self.config.binding(code) {
+                Some(binding) => binding.key_bondings()?,
+                None => continue,
+            };
+            let watch = Watch::start();
+            for key_bonding in key_bondings {
+                self.read_pending()?;
+                // The emergency stop abandons the binding, the macro pad keeps running.
+                if watch.check().is_err() {
+                    break;
+                }
+                self.keyboard.run_action(key_bonding)?;
+            }
+        }
+        Ok(())
+    }
+    fn read_pending(&mut self) -> Result<(), String> {
+        for source in &mut self.sources {
+            for event in source.device.read_events()? {
+                let transitions =
+                    emergency::feed_grabbed(&mut source.tracker, &source.device, &event)?;
+                for (_, code, pressed) in transitions {
+                    if pressed {
+                        self.pending.push_back(code);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+    /// Send the bindings until `stop` is set, the configuration file is checked between waits.
+    pub fn run_until(&mut self, stop: &AtomicBool) -> Result<(), String> {
+        while !stop.load(Ordering::SeqCst) {
+            let devices: Vec<&EvdevDevice> =
+                self.sources.iter().map(|source| &source.device).collect();
+            evdev::wait_events(&devices, WAIT_INTERVAL)?;
+            self.poll()?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for MacroPad {
+    fn drop(&mut self) {
+        for source in &mut self.sources {
+            source.device.grab(false).ok();
+        }
+    }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn config_reads_device_and_bindings() {
+        let config = MacroPadConfig::parse(
+            r#"
+            [device]
+            vendor = 0x04d9
+            product = 0x1603
+
+            [keys]
+            KeyKP1 = { keys = ["KeyC"], ctrl = true }
+            KeyKP2 = { sequence = [{ keys = ["KeyA"], ctrl = true }, { keys = ["KeyC"], ctrl = true }] }
+            KeyKP3 = { text = "Hi!" }
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.device,
+            DeviceSelector::Id {
+                vendor: 0x04d9,
+                product: 0x1603
+            }
+        );
+        let copy = KeyBonding {
+            has_ctrl: true,
+            keys: vec![KeyboardKey::KeyC],
+            ..KeyBonding::default()
+        };
+        assert_eq!(
+            config.binding(KeyboardKey::KeyKP1 as u16),
+            Some(&Binding::Bonding(copy.clone()))
+        );
+        match config.binding(KeyboardKey::KeyKP2 as u16) {
+            Some(Binding::Sequence(sequence)) => assert_eq!(sequence[1], copy),
+            other => panic!("{:?}", other),
+        }
+        assert_eq!(
+            config
+                .binding(KeyboardKey::KeyKP3 as u16)
+                .unwrap()
+                .key_bondings()
+                .unwrap()
+                .len(),
+            3
+        );
+
+        assert!(MacroPadConfig::parse("[device]\nname = \"pad\"\n[keys]\nKeyKP1 = {}\n").is_err());
+        assert!(MacroPadConfig::parse("[device]\nvendor = 1\n").is_err());
+        assert!(MacroPadConfig::parse(
+            "[device]\nname = \"pad\"\n[keys]\nKeyNope = { text = \"x\" }\n"
+        )
+        .is_err());
+    }
+}
@@ -0,0 +1,310 @@
+//! Virtual mouse on Linux, created with the same uinput plumbing as `LinuxKeyBD`.
+use linux::{
+    bonding_modifiers, AbsInfo, UInputBuilder, UInputDevice, DEVICE_NAME, EV_ABS, EV_KEY, EV_REL,
+    MODIFIERS,
+};
+use KeyBonding;
+
+pub const REL_X: u16 = 0x00;
+pub const REL_Y: u16 = 0x01;
+pub const REL_HWHEEL: u16 = 0x06;
+pub const REL_WHEEL: u16 = 0x08;
+pub const REL_WHEEL_HI_RES: u16 = 0x0b;
+pub const REL_HWHEEL_HI_RES: u16 = 0x0c;
+
+pub const ABS_X: u16 = 0x00;
+pub const ABS_Y: u16 = 0x01;
+
+/// High-resolution wheel units of one wheel detent.
+pub const WHEEL_DETENT: i32 = 120;
+
+/// Buttons of the virtual mouse.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum MouseButton {
+    Left = 0x110,
+    Right = 0x111,
+    Middle = 0x112,
+    Side = 0x113,
+    Extra = 0x114,
+}
+
+const BUTTONS: [MouseButton; 5] = [
+    MouseButton::Left,
+    MouseButton::Right,
+    MouseButton::Middle,
+    MouseButton::Side,
+    MouseButton::Extra,
+];
+
+/// Options of the Linux virtual mouse.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct MouseConfig {
+    /// Screen width and height for an absolute pointer, `None` for a relative mouse.
+    pub screen: Option<(i32, i32)>,
+    /// Create a companion keyboard holding the modifiers of `LinuxMouse::with_modifiers`.
+    ///
+    /// The mouse itself advertises only its buttons, so it is never classified as a keyboard.
+    pub modifiers: bool,
+}
+
+pub struct LinuxMouse {
+    instance: UInputDevice,
+    screen: Option<(i32, i32)>,
+    /// Companion keyboard of the modifiers.
+    modifiers: Option<UInputDevice>,
+    /// High-resolution units not reported yet as a detent, vertical then horizontal.
+    wheel_remainder: (i32, i32),
+}
+
+impl LinuxMouse {
+    /// Create a relative mouse.
+    pub fn new() -> Result<LinuxMouse, String> {
+        LinuxMouse::new_with_config(MouseConfig::default())
+    }
+    /// Create a mouse with the given options.
+    pub fn new_with_config(config: MouseConfig) -> Result<LinuxMouse, String> {
+        let mut builder = UInputBuilder::new(&format!("{} mouse", DEVICE_NAME))
+            .rel(REL_WHEEL)
+            .rel(REL_HWHEEL)
+            .rel(REL_WHEEL_HI_RES)
+            .rel(REL_HWHEEL_HI_RES);
+        builder = match config.screen {
+            None => builder.rel(REL_X).rel(REL_Y),
+            Some((width, height)) if width <= 0 || height <= 0 => {
+                return Err(format!("screen {}x{} has no position", width, height));
+            }
+            Some((width, height)) => builder
+                .abs(
+                    ABS_X,
+                    AbsInfo {
+                        maximum: width - 1,
+                        ..AbsInfo::default()
+                    },
+                )
+                .abs(
+                    ABS_Y,
+                    AbsInfo {
+                        maximum: height - 1,
+                        ..AbsInfo::default()
+                    },
+                ),
+        };
+        for button in BUTTONS.iter() {
+            builder = builder.key(*button as u16);
+        }
+        let modifiers = if config.modifiers {
+            let device = MODIFIERS
+                .iter()
+                .fold(
+                    UInputBuilder::new(&format!("{} mouse modifiers", DEVICE_NAME)),
+                    |builder, key| builder.key(*key as u16),
+                )
+                .create()?;
+            Some(device)
+        } else {
+            None
+        };
+        Ok(LinuxMouse::with_device(
+            builder.create()?,
+            config.screen,
+            modifiers,
+        ))
+    }
+    fn with_device(
+        instance: UInputDevice,
+        screen: Option<(i32, i32)>,
+        modifiers: Option<UInputDevice>,
+    ) -> LinuxMouse {
+        LinuxMouse {
+            instance,
+            screen,
+            modifiers,
+            wheel_remainder: (0, 0),
+        }
+    }
+    /// Move the pointer by `dx`, `dy`, the mouse must be relative.
+    pub fn move_relative(&mut self, dx: i32, dy: i32) -> Result<(), String> {
+        if self.screen.is_some() {
+            return Err("relative motion needs a relative mouse".to_string());
+        }
+        self.instance.write_event(EV_REL, REL_X, dx)?;
+        self.instance.write_event(EV_REL, REL_Y, dy)?;
+        self.frame()
+    }
+    /// Move the pointer to `x`, `y` on the screen, the mouse must be absolute.
+    pub fn move_to(&mut self, x: i32, y: i32) -> Result<(), String> {
+        let (width, height) = match self.screen {
+            Some(screen) => screen,
+            None => return Err("absolute positioning needs a screen size".to_string()),
+        };
+        if x < 0 || y < 0 || x >= width || y >= height {
+            return Err(format!("position {}x{} is out of the screen", x, y));
+        }
+        self.instance.write_event(EV_ABS, ABS_X, x)?;
+        self.instance.write_event(EV_ABS, ABS_Y, y)?;
+        self.frame()
+    }
+    pub fn press(&mut self, button: MouseButton) -> Result<(), String> {
+        self.instance.write_event(EV_KEY, button as u16, 1)?;
+        self.frame()
+    }
+    pub fn release(&mut self, button: MouseButton) -> Result<(), String> {
+        self.instance.write_event(EV_KEY, button as u16, 0)?;
+        self.frame()
+    }
+    pub fn click(&mut self, button: MouseButton) -> Result<(), String> {
+        self.press(button)?;
+        self.release(button)
+    }
+    /// Press the button, move by `dx`, `dy` and release it.
+    pub fn drag_relative(&mut self, button: MouseButton, dx: i32, dy: i32) -> Result<(), String> {
+        self.press(button)?;
+        self.move_relative(dx, dy)?;
+        self.release(button)
+    }
+    /// Press the button, move to `x`, `y` and release it.
+    pub fn drag_to(&mut self, button: MouseButton, x: i32, y: i32) -> Result<(), String> {
+        self.press(button)?;
+        self.move_to(x, y)?;
+        self.release(button)
+    }
+    /// Scroll by wheel detents, positive is up.
+    pub fn scroll(&mut self, detents: i32) -> Result<(), String> {
+        self.instance.write_event(EV_REL, REL_WHEEL, detents)?;
+        self.instance
+            .write_event(EV_REL, REL_WHEEL_HI_RES, detents * WHEEL_DETENT)?;
+        self.frame()
+    }
+    /// Scroll horizontally by wheel detents, positive is right.
+    pub fn scroll_horizontal(&mut self, detents: i32) -> Result<(), String> {
+        self.instance.write_event(EV_REL, REL_HWHEEL, detents)?;
+        self.instance
+            .write_event(EV_REL, REL_HWHEEL_HI_RES, detents * WHEEL_DETENT)?;
+        self.frame()
+    }
+    /// Scroll by high-resolution units, `WHEEL_DETENT` units make a detent.
+    pub fn scroll_hi_res(&mut self, units: i32) -> Result<(), String> {
+        let (remainder, detents) = split_detents(self.wheel_remainder.0 + units);
+        self.wheel_remainder.0 = remainder;
+        self.write_hi_res(REL_WHEEL, REL_WHEEL_HI_RES, units, detents)
+    }
+    /// Scroll horizontally by high-resolution units.
+    pub fn scroll_horizontal_hi_res(&mut self, units: i32) -> Result<(), String> {
+        let (remainder, detents) = split_detents(self.wheel_remainder.1 + units);
+        self.wheel_remainder.1 = remainder;
+        self.write_hi_res(REL_HWHEEL, REL_HWHEEL_HI_RES, units, detents)
+    }
+    /// Run `action` with the modifiers of the `KeyBonding` held, as Ctrl+click or Shift+drag.
+    ///
+    /// The modifiers are held on the companion keyboard of `MouseConfig::modifiers`.
+    pub fn with_modifiers<F>(&mut self, key_bonding: &KeyBonding, action: F) -> Result<(), String>
+    where
+        F: FnOnce(&mut LinuxMouse) -> Result<(), String>,
+    {
+        let modifiers = bonding_modifiers(key_bonding);
+        self.write_modifiers(&modifiers, 1)?;
+        let result = action(self);
+        // Modifiers are released even if the action failed.
+        let released: Vec<u16> = modifiers.into_iter().rev().collect();
+        self.write_modifiers(&released, 0)?;
+        result
+    }
+    fn write_modifiers(&mut self, modifiers: &[u16], value: i32) -> Result<(), String> {
+        let device = match self.modifiers {
+            Some(ref mut device) => device,
+            None if modifiers.is_empty() => return Ok(()),
+            None => return Err("holding modifiers needs MouseConfig::modifiers".to_string()),
+        };
+        for key in modifiers.iter() {
+            device.write_event(EV_KEY, *key, value)?;
+            device.synchronize()?;
+        }
+        device.flush()
+    }
+    fn write_hi_res(
+        &mut self,
+        code: u16,
+        hi_res: u16,
+        units: i32,
+        detents: i32,
+    ) -> Result<(), String> {
+        if detents != 0 {
+            self.instance.write_event(EV_REL, code, detents)?;
+        }
+        self.instance.write_event(EV_REL, hi_res, units)?;
+        self.frame()
+    }
+    fn frame(&mut self) -> Result<(), String> {
+        self.instance.synchronize()?;
+        self.instance.flush()
+    }
+}
+
+/// Split accumulated units in whole detents and the remainder.
+fn split_detents(units: i32) -> (i32, i32) {
+    (units % WHEEL_DETENT, units / WHEEL_DETENT)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+
+    use super::*;
+    use linux::tests::{read_events, stand_in};
+    use linux::EV_SYN;
+
+    #[test]
+    fn hi_res_wheel_reports_detents() {
+        let path = stand_in("mouse");
+        let modifiers_path = stand_in("mouse-modifiers");
+        {
+            let file = File::create(&path).unwrap();
+            let modifiers = File::create(&modifiers_path).unwrap();
+            let mut mouse = LinuxMouse::with_device(
+                UInputDevice::from_file(file),
+                None,
+                Some(UInputDevice::from_file(modifiers)),
+            );
+            let ctrl = KeyBonding {
+                has_ctrl: true,
+                has_alt: false,
+                has_shift: false,
+                has_rctrl: false,
+                has_rshift: false,
+                has_altgr: false,
+                keys: vec![],
+            };
+            mouse.scroll_hi_res(60).unwrap();
+            mouse.scroll_hi_res(90).unwrap();
+            mouse
+                .with_modifiers(&ctrl, |mouse| mouse.click(MouseButton::Left))
+                .unwrap();
+        }
+        let events = |path| -> Vec<(u16, u16, i32)> {
+            read_events(path)
+                .into_iter()
+                .filter(|event| event.0 != EV_SYN)
+                .map(|(kind, code, value, _, _)| (kind, code, value))
+                .collect()
+        };
+        assert_eq!(
+            events(&path),
+            vec![
+                (EV_REL, REL_WHEEL_HI_RES, 60),
+                (EV_REL, REL_WHEEL, 1),
+                (EV_REL, REL_WHEEL_HI_RES, 90),
+                (EV_KEY, MouseButton::Left as u16, 1),
+                (EV_KEY, MouseButton::Left as u16, 0),
+            ]
+        );
+        assert_eq!(
+            events(&modifiers_path),
+            vec![(EV_KEY, 29, 1), (EV_KEY, 29, 0)]
+        );
+        assert!(LinuxMouse::new_with_config(MouseConfig {
+            screen: Some((0, 1080)),
+            modifiers: false,
+        })
+        .is_err());
+    }
+}
@@ -0,0 +1,353 @@
+//! Virtual pen tablet on Linux, with pressure, tilt, eraser and barrel buttons.
+use std::f64::consts::PI;
+use std::thread::sleep;
+use std::time::Duration;
+
+use linux::{
+    AbsInfo, UInputBuilder, UInputDevice, DEVICE_NAME, EV_ABS, EV_KEY, INPUT_PROP_DIRECT,
+    INPUT_PROP_POINTER,
+};
+
+pub const ABS_X: u16 = 0x00;
+pub const ABS_Y: u16 = 0x01;
+pub const ABS_PRESSURE: u16 = 0x18;
+pub const ABS_TILT_X: u16 = 0x1a;
+pub const ABS_TILT_Y: u16 = 0x1b;
+
+pub const BTN_TOOL_PEN: u16 = 0x140;
+pub const BTN_TOOL_RUBBER: u16 = 0x141;
+pub const BTN_TOUCH: u16 = 0x14a;
+pub const BTN_STYLUS: u16 = 0x14b;
+pub const BTN_STYLUS2: u16 = 0x14c;
+
+/// Tip of the stylus in proximity.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum PenTool {
+    Pen = 0x140,
+    Eraser = 0x141,
+}
+
+/// Barrel buttons of the stylus.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum PenButton {
+    Barrel = 0x14b,
+    Barrel2 = 0x14c,
+}
+
+/// Pressure along a stroke, from 0 (no pressure) to 1 (full pressure).
+#[derive(Clone, Debug, PartialEq)]
+pub enum PressureCurve {
+    Constant(f64),
+    /// From the first value at the start to the second at the end.
+    Linear(f64, f64),
+    /// Rising from 0 and falling back to 0, peaking at the value in the middle.
+    Taper(f64),
+    /// Values spread evenly along the stroke, interpolated between them.
+    Points(Vec<f64>),
+}
+
+impl PressureCurve {
+    /// Pressure at `t`, from 0 at the start of the stroke to 1 at the end.
+    pub fn at(&self, t: f64) -> f64 {
+        let t = t.clamp(0.0, 1.0);
+        let pressure = match *self {
+            PressureCurve::Constant(value) => value,
+            PressureCurve::Linear(from, to) => from + (to - from) * t,
+            PressureCurve::Taper(peak) => peak * (PI * t).sin(),
+            PressureCurve::Points(ref points) => match points.len() {
+                0 => 0.0,
+                1 => points[0],
+                len => {
+                    let position = t * (len - 1) as f64;
+                    let index = (position.floor() as usize).min(len - 2);
+                    let ratio = position - index as f64;
+                    points[index] + (points[index + 1] - points[index]) * ratio
+                }
+            },
+        };
+        pressure.clamp(0.0, 1.0)
+    }
+}
+
+/// Options of a stroke.
+#[derive(Clone, Debug)]
+pub struct Stroke {
+    pub tool: PenTool,
+    pub pressure: PressureCurve,
+    /// Tilt of the stylus in degrees, X then Y.
+    pub tilt: (i32, i32),
+    pub duration: Duration,
+    /// Time between two frames of the stroke.
+    pub frame_interval: Duration,
+}
+
+impl Default for Stroke {
+    fn default() -> Stroke {
+        Stroke {
+            tool: PenTool::Pen,
+            pressure: PressureCurve::Taper(0.8),
+            tilt: (0, 0),
+            duration: Duration::from_millis(300),
+            frame_interval: Duration::from_millis(5),
+        }
+    }
+}
+
+/// Options of the Linux virtual pen tablet.
+#[derive(Copy, Clone, Debug)]
+pub struct PenConfig {
+    pub width: i32,
+    pub height: i32,
+    /// Units by millimeter of the X and Y axes.
+    pub resolution: i32,
+    pub max_pressure: i32,
+    /// The tablet is a screen (`INPUT_PROP_DIRECT`), otherwise it moves a pointer.
+    pub direct: bool,
+}
+
+impl Default for PenConfig {
+    fn default() -> PenConfig {
+        PenConfig {
+            width: 1920,
+            height: 1080,
+            resolution: 10,
+            max_pressure: 4095,
+            direct: true,
+        }
+    }
+}
+
+pub struct LinuxPen {
+    instance: UInputDevice,
+    config: PenConfig,
+    tool: Option<PenTool>,
+}
+
+impl LinuxPen {
+    /// Create a 1920x1080 screen tablet.
+    pub fn new() -> Result<LinuxPen, String> {
+        LinuxPen::new_with_config(PenConfig::default())
+    }
+    /// Create a pen tablet with the given options.
+    pub fn new_with_config(config: PenConfig) -> Result<LinuxPen, String> {
+        if config.width <= 0 || config.height <= 0 {
+            return Err(format!(
+                "screen {}x{} has no position",
+                config.width, config.height
+            ));
+        }
+        if config.max_pressure <= 0 {
+            return Err(format!("invalid maximum pressure {}", config.max_pressure));
+        }
+        let tilt = AbsInfo {
+            minimum: -90,
+            maximum: 90,
+            ..AbsInfo::default()
+        };
+        let builder = UInputBuilder::new(&format!("{} pen", DEVICE_NAME))
+            .property(if config.direct {
+                INPUT_PROP_DIRECT
+            } else {
+                INPUT_PROP_POINTER
+            })
+            .keys(&[
+                BTN_TOOL_PEN,
+                BTN_TOOL_RUBBER,
+                BTN_TOUCH,
+                BTN_STYLUS,
+                BTN_STYLUS2,
+            ])
+            .abs(
+                ABS_X,
+                AbsInfo {
+                    maximum: config.width - 1,
+                    resolution: config.resolution,
+                    ..AbsInfo::default()
+                },
+            )
+            .abs(
+                ABS_Y,
+                AbsInfo {
+                    maximum: config.height - 1,
+                    resolution: config.resolution,
+                    ..AbsInfo::default()
+                },
+            )
+            .abs(
+                ABS_PRESSURE,
+                AbsInfo {
+                    maximum: config.max_pressure,
+                    ..AbsInfo::default()
+                },
+            )
+            .abs(ABS_TILT_X, tilt)
+            .abs(ABS_TILT_Y, tilt);
+        Ok(LinuxPen::with_device(builder.create()?, config))
+    }
+    fn with_device(instance: UInputDevice, config: PenConfig) -> LinuxPen {
+        LinuxPen {
+            instance,
+            config,
+            tool: None,
+        }
+    }
+    /// Bring the tool in proximity above `x`, `y`.
+    pub fn hover(&mut self, tool: PenTool, x: i32, y: i32) -> Result<(), String> {
+        if let Some(current) = self.tool {
+            if current != tool {
+                self.leave()?;
+            }
+        }
+        if self.tool.is_none() {
+            self.instance.write_event(EV_KEY, tool as u16, 1)?;
+            self.tool = Some(tool);
+        }
+        self.write_position(x, y)?;
+        self.frame()
+    }
+    /// Take the tool out of proximity.
+    pub fn leave(&mut self) -> Result<(), String> {
+        if let Some(tool) = self.tool.take() {
+            self.instance.write_event(EV_KEY, tool as u16, 0)?;
+            self.frame()?;
+        }
+        Ok(())
+    }
+    pub fn press_button(&mut self, button: PenButton) -> Result<(), String> {
+        self.instance.write_event(EV_KEY, button as u16, 1)?;
+        self.frame()
+    }
+    pub fn release_button(&mut self, button: PenButton) -> Result<(), String> {
+        self.instance.write_event(EV_KEY, button as u16, 0)?;
+        self.frame()
+    }
+    /// Draw the polyline going through `points` and take the tool out of proximity.
+    pub fn draw(&mut self, points: &[(i32, i32)], stroke: &Stroke) -> Result<(), String> {
+        let (first, last) = match (points.first(), points.last()) {
+            (Some(first), Some(last)) => (*first, *last),
+            _ => return Ok(()),
+        };
+        self.hover(stroke.tool, first.0, first.1)?;
+        self.instance
+            .write_event(EV_ABS, ABS_TILT_X, stroke.tilt.0)?;
+        self.instance
+            .write_event(EV_ABS, ABS_TILT_Y, stroke.tilt.1)?;
+        self.instance.write_event(EV_KEY, BTN_TOUCH, 1)?;
+        self.write_pressure(stroke.pressure.at(0.0))?;
+        self.frame()?;
+        let interval = stroke.frame_interval.as_micros().max(1);
+        let steps = (stroke.duration.as_micros() / interval).max(1) as u32;
+        for step in 1..steps + 1 {
+            sleep(stroke.frame_interval);
+            let t = step as f64 / steps as f64;
+            let (x, y) = point_along(points, t);
+            self.write_position(x, y)?;
+            self.write_pressure(stroke.pressure.at(t))?;
+            self.frame()?;
+        }
+        self.write_pressure(0.0)?;
+        self.instance.write_event(EV_KEY, BTN_TOUCH, 0)?;
+        self.frame()?;
+        self.hover(stroke.tool, last.0, last.1)?;
+        self.leave()
+    }
+    fn write_position(&mut self, x: i32, y: i32) -> Result<(), String> {
+        if x < 0 || y < 0 || x >= self.config.width || y >= self.config.height {
+            return Err(format!("position {}x{} is out of the tablet", x, y));
+        }
+        self.instance.write_event(EV_ABS, ABS_X, x)?;
+        self.instance.write_event(EV_ABS, ABS_Y, y)
+    }
+    fn write_pressure(&mut self, pressure: f64) -> Result<(), String> {
+        let value = (pressure * self.config.max_pressure as f64).round() as i32;
+        self.instance.write_event(EV_ABS, ABS_PRESSURE, value)
+    }
+    fn frame(&mut self) -> Result<(), String> {
+        self.instance.synchronize()?;
+        self.instance.flush()
+    }
+}
+
+/// Point at `t` of the length of the polyline, from 0 at the start to 1 at the end.
+fn point_along(points: &[(i32, i32)], t: f64) -> (i32, i32) {
+    let lengths: Vec<f64> = points
+        .windows(2)
+        .map(|pair| {
+            let (dx, dy) = (pair[1].0 - pair[0].0, pair[1].1 - pair[0].1);
+            ((dx * dx + dy * dy) as f64).sqrt()
+        })
+        .collect();
+    let mut remaining = lengths.iter().sum::<f64>() * t;
+    for (index, length) in lengths.iter().enumerate() {
+        if remaining <= *length && *length > 0.0 {
+            let ratio = remaining / length;
+            let (from, to) = (points[index], points[index + 1]);
+            return (
+                from.0 + ((to.0 - from.0) as f64 * ratio).round() as i32,
+                from.1 + ((to.1 - from.1) as f64 * ratio).round() as i32,
+            );
+        }
+        remaining -= length;
+    }
+    points[points.len() - 1]
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+
+    use super::*;
+    use linux::tests::{read_events, stand_in};
+
+    #[test]
+    fn stroke_follows_polyline_and_pressure() {
+        let path = stand_in("pen");
+        {
+            let file = File::create(&path).unwrap();
+            let mut pen =
+                LinuxPen::with_device(UInputDevice::from_file(file), PenConfig::default());
+            let stroke = Stroke {
+                pressure: PressureCurve::Linear(0.0, 1.0),
+                duration: Duration::from_millis(4),
+                frame_interval: Duration::from_millis(1),
+                ..Stroke::default()
+            };
+            pen.draw(&[(0, 0), (100, 0), (100, 100)], &stroke).unwrap();
+        }
+        let events = read_events(&path);
+        // Frames of the stroke are X, Y and pressure.
+        let samples: Vec<(i32, i32, i32)> = (2..events.len())
+            .filter(|&i| events[i].1 == ABS_PRESSURE && events[i - 2].1 == ABS_X)
+            .map(|i| (events[i - 2].2, events[i - 1].2, events[i].2))
+            .collect();
+        assert_eq!(
+            samples,
+            vec![
+                (50, 0, 1024),
+                (100, 0, 2048),
+                (100, 50, 3071),
+                (100, 100, 4095)
+            ]
+        );
+        let keys: Vec<(u16, i32)> = events
+            .iter()
+            .filter(|event| event.0 == EV_KEY)
+            .map(|event| (event.1, event.2))
+            .collect();
+        assert_eq!(
+            keys,
+            vec![
+                (BTN_TOOL_PEN, 1),
+                (BTN_TOUCH, 1),
+                (BTN_TOUCH, 0),
+                (BTN_TOOL_PEN, 0)
+            ]
+        );
+        assert_eq!(PressureCurve::Points(vec![0.0, 1.0, 0.0]).at(0.25), 0.5);
+        assert!(LinuxPen::new_with_config(PenConfig {
+            width: -1,
+            ..PenConfig::default()
+        })
+        .is_err());
+    }
+}
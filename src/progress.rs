@@ -0,0 +1,241 @@
+//! Cancellation and progress of the long operations: the sequences and texts of
+//! `KeyBondingInstance` and the playbacks of `timeline::Player`.
+//!
+//! A `CancelToken` is cancelled from any thread, an `Observer` follows the steps, a step being one
+//! `KeyBonding`, and the operation returns a `Completion` telling how far it got.
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use emergency::{Watch, STOPPED};
+use {us_layout_char, KeyBonding};
+
+/// Error of the operations cancelled by their `CancelToken`.
+pub const CANCELLED: &str = "cancelled";
+
+/// Token cancelling an operation from another thread, the clones share the cancellation.
+#[derive(Clone, Debug, Default)]
+pub struct CancelToken {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancelToken {
+    pub fn new() -> CancelToken {
+        CancelToken::default()
+    }
+    /// Cancel the operations using the token, before their next step.
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+}
+
+/// Progress of an operation.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Progress {
+    pub steps_done: usize,
+    /// `None` for a playback looping forever.
+    pub steps_total: Option<usize>,
+    /// Characters typed by the steps done, the `KeyBonding`s typing a character of the US layout.
+    pub characters_typed: usize,
+    pub elapsed: Duration,
+    /// Estimated time before the end.
+    pub eta: Option<Duration>,
+}
+
+/// Observer of the steps of an operation, the methods do nothing by default.
+///
+/// `()` is the observer following nothing.
+pub trait Observer {
+    /// The step `index` is about to be launched.
+    fn step_started(&mut self, _index: usize, _key_bonding: &KeyBonding) {}
+    /// The step `index` was launched.
+    fn step_finished(&mut self, _index: usize, _progress: &Progress) {}
+}
+
+impl Observer for () {}
+
+/// How an operation ended.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Outcome {
+    Finished,
+    /// Cancelled by its `CancelToken`.
+    Cancelled,
+    /// Cancelled by the emergency stop.
+    Stopped,
+    Failed(String),
+}
+
+/// How far an operation got.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Completion {
+    pub progress: Progress,
+    pub outcome: Outcome,
+}
+
+impl Completion {
+    pub fn is_finished(&self) -> bool {
+        self.outcome == Outcome::Finished
+    }
+    /// `Ok` when finished, otherwise the error, `CANCELLED` or `emergency::STOPPED`.
+    pub fn into_result(self) -> Result<(), String> {
+        match self.outcome {
+            Outcome::Finished => Ok(()),
+            Outcome::Cancelled => Err(CANCELLED.to_string()),
+            Outcome::Stopped => Err(STOPPED.to_string()),
+            Outcome::Failed(error) => Err(error),
+        }
+    }
+}
+
+/// Whether `key_bonding` types a character of the US layout.
+fn types_character(key_bonding: &KeyBonding) -> bool {
+    let shortcut = key_bonding.has_ctrl
+        || key_bonding.has_alt
+        || key_bonding.has_rctrl
+        || key_bonding.has_altgr;
+    let shift = key_bonding.has_shift || key_bonding.has_rshift;
+    match key_bonding.keys[..] {
+        [key] if !shortcut => us_layout_char(key as u16, shift).is_some(),
+        _ => false,
+    }
+}
+
+/// Steps of a running operation, following its token, the emergency stop and its observer.
+pub(crate) struct Run<'a> {
+    cancel: &'a CancelToken,
+    observer: &'a mut dyn Observer,
+    watch: Watch,
+    start: Instant,
+    end: Option<Instant>,
+    progress: Progress,
+}
+
+impl<'a> Run<'a> {
+    pub(crate) fn new(
+        cancel: &'a CancelToken,
+        observer: &'a mut dyn Observer,
+        steps_total: Option<usize>,
+    ) -> Run<'a> {
+        Run {
+            cancel,
+            observer,
+            watch: Watch::start(),
+            start: Instant::now(),
+            end: None,
+            progress: Progress {
+                steps_total,
+                ..Progress::default()
+            },
+        }
+    }
+    /// The expected end, for the ETA, otherwise the ETA assumes the steps last as the done ones.
+    pub(crate) fn expect_end(&mut self, end: Instant) {
+        self.end = Some(end);
+    }
+    pub(crate) fn check(&self) -> Result<(), Outcome> {
+        if self.cancel.is_cancelled() {
+            return Err(Outcome::Cancelled);
+        }
+        self.watch.check().map_err(|_| Outcome::Stopped)
+    }
+    /// Sleep during `duration`, fails as soon as the operation is cancelled.
+    pub(crate) fn sleep(&self, duration: Duration) -> Result<(), Outcome> {
+        self.watch.sleep_checked(duration, || self.check())
+    }
+    /// Launch `key_bonding` with `launch`, unless the operation is cancelled.
+    pub(crate) fn step<F>(&mut self, key_bonding: KeyBonding, launch: F) -> Result<(), Outcome>
+    where
+        F: FnOnce(KeyBonding) -> Result<(), String>,
+    {
+        self.check()?;
+        let index = self.progress.steps_done;
+        self.observer.step_started(index, &key_bonding);
+        let typed = types_character(&key_bonding);
+        launch(key_bonding).map_err(Outcome::Failed)?;
+        let now = Instant::now();
+        let progress = &mut self.progress;
+        progress.steps_done += 1;
+        progress.characters_typed += typed as usize;
+        progress.elapsed = now - self.start;
+        progress.eta = match (self.end, progress.steps_total) {
+            (Some(end), _) => Some(end.saturating_duration_since(now)),
+            (None, Some(total)) => {
+                let remaining = total.saturating_sub(progress.steps_done) as u32;
+                Some(progress.elapsed / progress.steps_done as u32 * remaining)
+            }
+            (None, None) => None,
+        };
+        self.observer.step_finished(index, &self.progress);
+        Ok(())
+    }
+    pub(crate) fn finish(self, result: Result<(), Outcome>) -> Completion {
+        Completion {
+            progress: self.progress,
+            outcome: result.err().unwrap_or(Outcome::Finished),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use KeyboardKey;
+
+    #[derive(Default)]
+    struct Steps {
+        started: Vec<usize>,
+        finished: Vec<Progress>,
+    }
+
+    impl Observer for Steps {
+        fn step_started(&mut self, index: usize, _key_bonding: &KeyBonding) {
+            self.started.push(index);
+        }
+        fn step_finished(&mut self, _index: usize, progress: &Progress) {
+            self.finished.push(progress.clone());
+        }
+    }
+
+    #[test]
+    fn run_reports_progress_until_cancelled() {
+        let mut key_bondings = KeyBonding::from_text("ab").unwrap();
+        key_bondings.push(KeyBonding {
+            has_ctrl: true,
+            keys: vec![KeyboardKey::KeyC],
+            ..KeyBonding::default()
+        });
+        key_bondings.push(key_bondings[0].clone());
+        let cancel = CancelToken::new();
+        let mut steps = Steps::default();
+        let mut launched = vec![];
+        let completion = {
+            let mut run = Run::new(&cancel, &mut steps, Some(key_bondings.len()));
+            let mut result = Ok(());
+            for key_bonding in key_bondings {
+                result = run.step(key_bonding, |key_bonding| {
+                    launched.push(key_bonding);
+                    if launched.len() == 3 {
+                        cancel.cancel();
+                    }
+                    Ok(())
+                });
+                if result.is_err() {
+                    break;
+                }
+            }
+            run.finish(result)
+        };
+        assert_eq!(completion.outcome, Outcome::Cancelled);
+        assert_eq!(completion.progress.steps_done, 3);
+        assert_eq!(completion.progress.characters_typed, 2);
+        assert_eq!(launched.len(), 3);
+        assert_eq!(steps.started, vec![0, 1, 2]);
+        assert_eq!(steps.finished.len(), 3);
+        assert_eq!(steps.finished[0].steps_total, Some(4));
+        assert!(steps.finished[2].eta.is_some());
+        assert_eq!(completion.into_result(), Err(CANCELLED.to_string()));
+    }
+}
@@ -0,0 +1,257 @@
+//! Recording the keys typed on physical keyboards, for playing them back later.
+//!
+//! The events are read from `/dev/input/event*`, so it needs the root user or the `input` group.
+use std::path::PathBuf;
+use std::thread::sleep;
+use std::time::{Duration, Instant, SystemTime};
+
+use evdev::{self, EvdevDevice};
+use linux::{InputEvent, EV_KEY, EV_SYN, SYN_DROPPED, SYN_REPORT};
+use timeline::{KeyEvent, TimedKeyEvent};
+
+const POLL_INTERVAL: Duration = Duration::from_millis(5);
+
+/// Selection of the devices to record.
+#[derive(Clone, Debug, PartialEq)]
+pub enum DeviceSelector {
+    /// The event node, such as `/dev/input/event3`.
+    Path(PathBuf),
+    /// The devices with this name.
+    Name(String),
+    /// The devices with this USB vendor and product.
+    Id { vendor: u16, product: u16 },
+    /// Every physical keyboard.
+    Keyboards,
+}
+
+impl DeviceSelector {
+    fn matches(&self, device: &EvdevDevice) -> bool {
+        match *self {
+            DeviceSelector::Path(ref path) => device.path() == path.as_path(),
+            DeviceSelector::Name(ref name) => device.name() == name,
+            DeviceSelector::Id { vendor, product } => {
+                device.id().vendor == vendor && device.id().product == product
+            }
+            DeviceSelector::Keyboards => device.is_keyboard(),
+        }
+    }
+}
+
+/// Open the devices matching any of `selectors`, the virtual devices of this crate are skipped.
+///
+/// Fails if a selector matches no device.
+pub(crate) fn select_devices(selectors: &[DeviceSelector]) -> Result<Vec<EvdevDevice>, String> {
+    let mut devices = vec![];
+    for selector in selectors {
+        if let DeviceSelector::Path(ref path) = *selector {
+            devices.push(EvdevDevice::open(path)?);
+        }
+    }
+    let mut found = evdev::devices()
+        .into_iter()
+        .filter(|device| !device.is_own_device())
+        .filter(|device| devices.iter().all(|opened| opened.path() != device.path()))
+        .filter(|device| {
+            selectors.iter().any(|selector| match *selector {
+                DeviceSelector::Path(_) => false,
+                _ => selector.matches(device),
+            })
+        })
+        .collect();
+    devices.append(&mut found);
+    for selector in selectors {
+        if !devices.iter().any(|device| selector.matches(device)) {
+            return Err(format!("no input device matches {:?}", selector));
+        }
+    }
+    Ok(devices)
+}
+
+/// Key state of one device, rebuilt from its events.
+#[derive(Default)]
+pub(crate) struct KeyTracker {
+    pub(crate) held: Vec<u16>,
+    frame: Vec<(SystemTime, u16, bool)>,
+    dropped: bool,
+}
+
+impl KeyTracker {
+    /// A tracker of a device with the keys `held` down.
+    pub(crate) fn new(held: Vec<u16>) -> KeyTracker {
+        KeyTracker {
+            held,
+            ..KeyTracker::default()
+        }
+    }
+    /// Feed an event, the key transitions are returned when its frame is complete.
+    ///
+    /// After `SYN_DROPPED` the events are discarded up to the next `SYN_REPORT`, then the
+    /// transitions are deduced from `key_state`, the keys currently held on the device.
+    pub(crate) fn feed<F>(
+        &mut self,
+        event: &InputEvent,
+        key_state: F,
+    ) -> Result<Vec<(SystemTime, u16, bool)>, String>
+    where
+        F: FnOnce() -> Result<Vec<u16>, String>,
+    {
+        match (event.kind, event.code) {
+            (EV_SYN, SYN_DROPPED) => {
+                self.frame.clear();
+                self.dropped = true;
+                Ok(vec![])
+            }
+            (EV_SYN, SYN_REPORT) if self.dropped => {
+                self.dropped = false;
+                let time = event.system_time();
+                let state = key_state()?;
+                let mut transitions: Vec<(SystemTime, u16, bool)> = self
+                    .held
+                    .iter()
+                    .filter(|code| !state.contains(code))
+                    .map(|code| (time, *code, false))
+                    .collect();
+                transitions.extend(
+                    state
+                        .iter()
+                        .filter(|code| !self.held.contains(code))
+                        .map(|code| (time, *code, true)),
+                );
+                self.held = state;
+                Ok(transitions)
+            }
+            (EV_SYN, SYN_REPORT) => {
+                for &(_, code, pressed) in &self.frame {
+                    self.held.retain(|held| *held != code);
+                    if pressed {
+                        self.held.push(code);
+                    }
+                }
+                Ok(self.frame.drain(..).collect())
+            }
+            // The autorepeats (value 2) are not transitions.
+            (EV_KEY, code) if !self.dropped && event.value != 2 => {
+                self.frame
+                    .push((event.system_time(), code, event.value == 1));
+                Ok(vec![])
+            }
+            _ => Ok(vec![]),
+        }
+    }
+}
+
+struct Source {
+    device: EvdevDevice,
+    tracker: KeyTracker,
+}
+
+/// Recorder of the keys and modifiers typed on physical keyboards.
+///
+/// The keys without `KeyboardKey` or `Modifier`, such as the meta keys, are not recorded.
+pub struct Recorder {
+    sources: Vec<Source>,
+    start: SystemTime,
+    events: Vec<TimedKeyEvent>,
+}
+
+impl Recorder {
+    /// Open the devices matching any of `selectors`, the virtual devices of this crate are skipped.
+    ///
+    /// The recording starts now, the keys already held are not recorded as pressed.
+    pub fn open(selectors: &[DeviceSelector]) -> Result<Recorder, String> {
+        let mut sources = vec![];
+        for device in select_devices(selectors)? {
+            let tracker = KeyTracker::new(device.key_state()?);
+            sources.push(Source { device, tracker });
+        }
+        Ok(Recorder {
+            sources,
+            start: SystemTime::now(),
+            events: vec![],
+        })
+    }
+    /// Names of the recorded devices.
+    pub fn device_names(&self) -> Vec<&str> {
+        self.sources
+            .iter()
+            .map(|source| source.device.name())
+            .collect()
+    }
+    /// Read the pending events without blocking, returns the number of new recorded events.
+    pub fn poll(&mut self) -> Result<usize, String> {
+        let mut transitions = vec![];
+        for source in &mut self.sources {
+            let device = &mut source.device;
+            let tracker = &mut source.tracker;
+            for event in device.read_events()? {
+                transitions.extend(tracker.feed(&event, || device.key_state())?);
+            }
+        }
+        transitions.sort_by_key(|transition| transition.0);
+        let before = self.events.len();
+        for (time, code, pressed) in transitions {
+            if let Some(event) = KeyEvent::from_code(code, pressed) {
+                self.events.push(TimedKeyEvent {
+                    time: time.duration_since(self.start).unwrap_or_default(),
+                    event,
+                });
+            }
+        }
+        Ok(self.events.len() - before)
+    }
+    /// Record during `duration`.
+    pub fn record_for(&mut self, duration: Duration) -> Result<(), String> {
+        let end = Instant::now() + duration;
+        while Instant::now() < end {
+            self.poll()?;
+            sleep(POLL_INTERVAL);
+        }
+        self.poll().map(|_| ())
+    }
+    /// The events recorded so far, with their time from the opening of the recorder.
+    pub fn events(&self) -> &[TimedKeyEvent] {
+        &self.events
+    }
+    pub fn into_events(self) -> Vec<TimedKeyEvent> {
+        self.events
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn key_tracker_resyncs_after_dropped_events() {
+        let mut tracker = KeyTracker::default();
+        let no_state = || -> Result<Vec<u16>, String> { panic!("no resync without SYN_DROPPED") };
+        let mut transitions = vec![];
+        for event in &[
+            InputEvent::new(EV_KEY, 42, 1),
+            InputEvent::new(EV_KEY, 30, 1),
+            InputEvent::new(EV_SYN, SYN_REPORT, 0),
+            InputEvent::new(EV_KEY, 30, 2),
+            InputEvent::new(EV_SYN, SYN_REPORT, 0),
+        ] {
+            transitions.extend(tracker.feed(event, no_state).unwrap());
+        }
+        let codes: Vec<(u16, bool)> = transitions.iter().map(|t| (t.1, t.2)).collect();
+        assert_eq!(codes, vec![(42, true), (30, true)]);
+
+        // The release of A and the press of B are lost, Shift is still held.
+        assert!(tracker
+            .feed(&InputEvent::new(EV_SYN, SYN_DROPPED, 0), no_state)
+            .unwrap()
+            .is_empty());
+        assert!(tracker
+            .feed(&InputEvent::new(EV_KEY, 48, 1), no_state)
+            .unwrap()
+            .is_empty());
+        let transitions = tracker
+            .feed(&InputEvent::new(EV_SYN, SYN_REPORT, 0), || Ok(vec![42, 48]))
+            .unwrap();
+        let codes: Vec<(u16, bool)> = transitions.iter().map(|t| (t.1, t.2)).collect();
+        assert_eq!(codes, vec![(30, false), (48, true)]);
+        assert_eq!(tracker.held, vec![42, 48]);
+    }
+}
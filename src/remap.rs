@@ -0,0 +1,238 @@
+//! Remapping a physical keyboard: the device is grabbed with `EVIOCGRAB` and its keys are sent
+//! again, mapped, from a `LinuxKeyBD` device.
+//!
+//! Reading and grabbing `/dev/input/event*` needs the root user or the `input` group.
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+use emergency::{self, Watch};
+use evdev::{self, EvdevDevice};
+use linux::{LinuxKeyBD, LinuxKeyBDConfig};
+use recorder::KeyTracker;
+use timeline::Modifier;
+use {KBPlatform, KeyBonding, KeyboardKey};
+
+const WAIT_INTERVAL: Duration = Duration::from_millis(100);
+
+/// What a mapped key does.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Remap {
+    /// Another key, held as long as the mapped key.
+    Key(KeyboardKey),
+    /// A modifier, held as long as the mapped key.
+    Modifier(Modifier),
+    /// A combo launched when the mapped key goes down.
+    Combo(KeyBonding),
+    /// Combos launched in order when the mapped key goes down.
+    Sequence(Vec<KeyBonding>),
+    /// Nothing.
+    Disabled,
+}
+
+/// Table of the mapped keys, the other keys are passed through.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RemapTable {
+    entries: Vec<(u16, Remap)>,
+}
+
+impl RemapTable {
+    pub fn new() -> RemapTable {
+        RemapTable::default()
+    }
+    pub fn map(self, from: KeyboardKey, to: Remap) -> RemapTable {
+        self.map_code(from as u16, to)
+    }
+    pub fn map_modifier(self, from: Modifier, to: Remap) -> RemapTable {
+        self.map_code(from as u16, to)
+    }
+    fn map_code(mut self, from: u16, to: Remap) -> RemapTable {
+        self.entries.retain(|&(code, _)| code != from);
+        self.entries.push((from, to));
+        self
+    }
+    /// What to send when the key `code` goes down (`value` 1) or up (`value` 0).
+    pub(crate) fn translate(&self, code: u16, value: i32) -> Vec<Output> {
+        let remap = match self.entries.iter().find(|&&(from, _)| from == code) {
+            Some((_, remap)) => remap,
+            None => return vec![Output::Transition(code, value)],
+        };
+        match *remap {
+            Remap::Key(key) => vec![Output::Transition(key as u16, value)],
+            Remap::Modifier(modifier) => vec![Output::Transition(modifier as u16, value)],
+            Remap::Combo(ref key_bonding) if value == 1 => {
+                vec![Output::Launch(key_bonding.clone())]
+            }
+            Remap::Sequence(ref key_bondings) if value == 1 => key_bondings
+                .iter()
+                .map(|key_bonding| Output::Launch(key_bonding.clone()))
+                .collect(),
+            _ => vec![],
+        }
+    }
+}
+
+/// Events sent for a key of the grabbed keyboard.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum Output {
+    Transition(u16, i32),
+    Launch(KeyBonding),
+}
+
+/// A grabbed keyboard sending its keys through a `RemapTable`.
+///
+/// The grab is released and the keys still held are released on drop. The emergency stop releases
+/// the keys held and abandons the combos and sequences, the remapper keeps running. The hotkey of
+/// a `KillSwitch` pressed on the grabbed keyboard calls the emergency stop.
+pub struct Remapper {
+    device: EvdevDevice,
+    tracker: KeyTracker,
+    table: RemapTable,
+    keyboard: LinuxKeyBD,
+    /// Keys held down on the virtual keyboard.
+    held: Vec<u16>,
+    /// Transitions read from the grabbed keyboard, waiting to be sent.
+    pending: VecDeque<(u16, bool)>,
+    watch: Watch,
+}
+
+impl Remapper {
+    /// Grab `device` and send its keys from a virtual keyboard.
+    ///
+    /// The grab waits until no key is held on `device`, so no key stays pressed for the desktop.
+    pub fn new(device: EvdevDevice, table: RemapTable) -> Result<Remapper, String> {
+        Remapper::with_config(device, table, LinuxKeyBDConfig::default())
+    }
+    /// Grab `device` and send its keys from a virtual keyboard with the given options.
+    ///
+    /// The autorepeats of the grabbed keyboard are not forwarded, the virtual keyboard advertises
+    /// `EV_REP` with `config.repeat`, or the default timing, and the kernel repeats its held keys.
+    pub fn with_config(
+        mut device: EvdevDevice,
+        table: RemapTable,
+        mut config: LinuxKeyBDConfig,
+    ) -> Result<Remapper, String> {
+        if device.is_own_device() {
+            return Err(format!(
+                "{}: a virtual device of this crate can not be remapped",
+                device.path().display()
+            ));
+        }
+        config.repeat = Some(config.repeat.unwrap_or_default());
+        let keyboard = LinuxKeyBD::create(config)?;
+        device.wait_keys_released()?;
+        device.grab(true)?;
+        Ok(Remapper {
+            device,
+            tracker: KeyTracker::new(vec![]),
+            table,
+            keyboard,
+            held: vec![],
+            pending: VecDeque::new(),
+            watch: Watch::start(),
+        })
+    }
+    /// Read the pending events without blocking and send the mapped keys.
+    pub fn poll(&mut self) -> Result<(), String> {
+        self.release_if_stopped()?;
+        self.read_pending()?;
+        while let Some((code, pressed)) = self.pending.pop_front() {
+            for output in self.table.translate(code, pressed as i32) {
+                // A `KillSwitch` hotkey pressed during a sequence is read between its combos.
+                self.read_pending()?;
+                if self.release_if_stopped()? {
+                    break;
+                }
+                self.send(output)?;
+            }
+            self.keyboard.flush()?;
+        }
+        Ok(())
+    }
+    fn read_pending(&mut self) -> Result<(), String> {
+        for event in self.device.read_events()? {
+            for (_, code, pressed) in
+                emergency::feed_grabbed(&mut self.tracker, &self.device, &event)?
+            {
+                self.pending.push_back((code, pressed));
+            }
+        }
+        Ok(())
+    }
+    /// Remap the keys until `stop` is set, the emergency stop is checked between waits.
+    pub fn run_until(&mut self, stop: &AtomicBool) -> Result<(), String> {
+        while !stop.load(Ordering::SeqCst) {
+            evdev::wait_events(&[&self.device], WAIT_INTERVAL)?;
+            self.poll()?;
+        }
+        Ok(())
+    }
+    /// Release the held keys if the emergency stop was pressed, then watch the next stops.
+    fn release_if_stopped(&mut self) -> Result<bool, String> {
+        if self.watch.check().is_ok() {
+            return Ok(false);
+        }
+        self.watch = Watch::start();
+        self.release_held()?;
+        Ok(true)
+    }
+    fn release_held(&mut self) -> Result<(), String> {
+        for code in self.held.drain(..).collect::<Vec<u16>>() {
+            self.keyboard.write_transition(code as u8, 0)?;
+        }
+        self.keyboard.flush()
+    }
+    fn send(&mut self, output: Output) -> Result<(), String> {
+        match output {
+            // Only the keys of the virtual keyboard, below the first button range, are sent.
+            Output::Transition(code, value) if code < 0x100 => {
+                self.held.retain(|held| *held != code);
+                if value != 0 {
+                    self.held.push(code);
+                }
+                self.keyboard.write_transition(code as u8, value)
+            }
+            Output::Transition(_, _) => Ok(()),
+            Output::Launch(key_bonding) => self.keyboard.run_action(key_bonding),
+        }
+    }
+}
+
+impl Drop for Remapper {
+    fn drop(&mut self) {
+        self.release_held().ok();
+        self.device.grab(false).ok();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn table_maps_keys_combos_and_sequences() {
+        let copy = KeyBonding {
+            has_ctrl: true,
+            keys: vec![KeyboardKey::KeyC],
+            ..KeyBonding::default()
+        };
+        let table = RemapTable::new()
+            .map(KeyboardKey::KeyCAPSLock, Remap::Modifier(Modifier::Ctrl))
+            .map(KeyboardKey::KeyF1, Remap::Combo(copy.clone()))
+            .map(
+                KeyboardKey::KeyF2,
+                Remap::Sequence(vec![copy.clone(), copy.clone()]),
+            )
+            .map(KeyboardKey::KeyF3, Remap::Disabled)
+            .map(KeyboardKey::KeyA, Remap::Key(KeyboardKey::KeyB))
+            .map(KeyboardKey::KeyA, Remap::Key(KeyboardKey::KeyQ));
+        assert_eq!(table.translate(58, 1), vec![Output::Transition(29, 1)]);
+        assert_eq!(table.translate(58, 0), vec![Output::Transition(29, 0)]);
+        assert_eq!(table.translate(59, 1), vec![Output::Launch(copy.clone())]);
+        assert!(table.translate(59, 0).is_empty());
+        assert_eq!(table.translate(60, 1).len(), 2);
+        assert!(table.translate(61, 1).is_empty());
+        assert_eq!(table.translate(30, 1), vec![Output::Transition(16, 1)]);
+        assert_eq!(table.translate(48, 0), vec![Output::Transition(48, 0)]);
+    }
+}
@@ -0,0 +1,119 @@
+//! Virtual switches on Linux, such as the lid, the tablet mode or the headphone jack.
+use linux::{UInputBuilder, UInputDevice, DEVICE_NAME, EV_SW};
+
+/// Switches of the `EV_SW` events, with their evdev codes.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Switch {
+    Lid = 0x00,
+    TabletMode = 0x01,
+    HeadphoneInsert = 0x02,
+    RfkillAll = 0x03,
+    MicrophoneInsert = 0x04,
+    Dock = 0x05,
+    LineoutInsert = 0x06,
+    JackPhysicalInsert = 0x07,
+    VideooutInsert = 0x08,
+    CameraLensCover = 0x09,
+    KeypadSlide = 0x0a,
+    FrontProximity = 0x0b,
+    RotateLock = 0x0c,
+    LineinInsert = 0x0d,
+    MuteDevice = 0x0e,
+    PenInserted = 0x0f,
+    MachineCover = 0x10,
+}
+
+pub struct LinuxSwitches {
+    instance: UInputDevice,
+    /// Advertised switches and their state.
+    switches: Vec<(Switch, bool)>,
+}
+
+impl LinuxSwitches {
+    /// Create a device advertising the given switches, all off.
+    pub fn new(switches: &[Switch]) -> Result<LinuxSwitches, String> {
+        let builder = switches.iter().fold(
+            UInputBuilder::new(&format!("{} switches", DEVICE_NAME)),
+            |builder, switch| builder.switch(*switch as u16),
+        );
+        Ok(LinuxSwitches::with_device(builder.create()?, switches))
+    }
+    fn with_device(instance: UInputDevice, switches: &[Switch]) -> LinuxSwitches {
+        let mut states: Vec<(Switch, bool)> = vec![];
+        for switch in switches {
+            if !states.iter().any(|&(other, _)| other == *switch) {
+                states.push((*switch, false));
+            }
+        }
+        LinuxSwitches {
+            instance,
+            switches: states,
+        }
+    }
+    /// State of the switch.
+    pub fn state(&self, switch: Switch) -> Result<bool, String> {
+        match self.switches.iter().find(|&&(other, _)| other == switch) {
+            Some(&(_, on)) => Ok(on),
+            None => Err(format!(
+                "switch {:?} is not advertised by the device",
+                switch
+            )),
+        }
+    }
+    /// Set the switch, nothing is sent if it is already in this state.
+    pub fn set(&mut self, switch: Switch, on: bool) -> Result<(), String> {
+        if self.state(switch)? == on {
+            return Ok(());
+        }
+        self.instance.write_event(EV_SW, switch as u16, on as i32)?;
+        self.instance.synchronize()?;
+        self.instance.flush()?;
+        for state in self.switches.iter_mut() {
+            if state.0 == switch {
+                state.1 = on;
+            }
+        }
+        Ok(())
+    }
+    /// Flip the switch and return its new state.
+    pub fn toggle(&mut self, switch: Switch) -> Result<bool, String> {
+        let on = !self.state(switch)?;
+        self.set(switch, on)?;
+        Ok(on)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+
+    use super::*;
+    use linux::tests::{read_events, stand_in};
+    use linux::EV_SYN;
+
+    #[test]
+    fn set_and_toggle_report_changes() {
+        let path = stand_in("switch");
+        {
+            let file = File::create(&path).unwrap();
+            let mut switches = LinuxSwitches::with_device(
+                UInputDevice::from_file(file),
+                &[Switch::Lid, Switch::TabletMode],
+            );
+            switches.set(Switch::Lid, true).unwrap();
+            switches.set(Switch::Lid, true).unwrap();
+            assert!(!switches.toggle(Switch::Lid).unwrap());
+            assert!(switches.toggle(Switch::TabletMode).unwrap());
+            assert!(switches.set(Switch::MuteDevice, true).is_err());
+        }
+        let events: Vec<(u16, u16, i32)> = read_events(&path)
+            .into_iter()
+            .filter(|event| event.0 != EV_SYN)
+            .map(|(kind, code, value, _, _)| (kind, code, value))
+            .collect();
+        assert_eq!(
+            events,
+            vec![(EV_SW, 0x00, 1), (EV_SW, 0x00, 0), (EV_SW, 0x01, 1)]
+        );
+    }
+}
@@ -0,0 +1,768 @@
+//! Timelines of key events, such as a recording of a physical keyboard, and their playback.
+//!
+//! # Text format
+//!
+//! ```text
+//! keybd_event timeline 1
+//! # milliseconds action key
+//! 0 press Shift
+//! 12.5 press KeyA
+//! 40 release KeyA
+//! 52 release Shift
+//! ```
+//!
+//! The first line gives the version of the format. Each event is a time in milliseconds from the
+//! start of the timeline, `press` or `release`, and the name of a `KeyboardKey` or a `Modifier`.
+//! The empty lines and the lines starting with `#` are ignored.
+//!
+//! With the feature `json`, the same timeline is also written as JSON:
+//!
+//! ```text
+//! {"version":1,"events":[{"time_ms":0.0,"action":"press","key":"Shift"}, ...]}
+//! ```
+use std::time::{Duration, Instant};
+
+#[cfg(feature = "json")]
+use serde::{Deserialize, Serialize};
+
+use progress::{CancelToken, Completion, Observer, Outcome, Run};
+use {KBPlatform, KeyBonding, KeyboardKey};
+
+/// Version of the text and JSON formats.
+pub const FORMAT_VERSION: u32 = 1;
+const TEXT_HEADER: &str = "keybd_event timeline";
+const MODIFIERS: [Modifier; 6] = [
+    Modifier::Ctrl,
+    Modifier::Shift,
+    Modifier::Alt,
+    Modifier::RCtrl,
+    Modifier::RShift,
+    Modifier::AltGr,
+];
+
+/// The modifiers of a `KeyBonding`, with their Linux key codes.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Modifier {
+    Ctrl = 29,
+    Shift = 42,
+    Alt = 56,
+    RCtrl = 97,
+    RShift = 54,
+    AltGr = 100,
+}
+
+impl Modifier {
+    /// The modifier of the Linux key code `code`.
+    pub fn from_code(code: u16) -> Option<Modifier> {
+        match code {
+            29 => Some(Modifier::Ctrl),
+            42 => Some(Modifier::Shift),
+            56 => Some(Modifier::Alt),
+            97 => Some(Modifier::RCtrl),
+            54 => Some(Modifier::RShift),
+            100 => Some(Modifier::AltGr),
+            _ => None,
+        }
+    }
+    /// Set the flag of the modifier in `key_bonding`.
+    pub(crate) fn apply(self, key_bonding: &mut KeyBonding) {
+        match self {
+            Modifier::Ctrl => key_bonding.has_ctrl = true,
+            Modifier::Shift => key_bonding.has_shift = true,
+            Modifier::Alt => key_bonding.has_alt = true,
+            Modifier::RCtrl => key_bonding.has_rctrl = true,
+            Modifier::RShift => key_bonding.has_rshift = true,
+            Modifier::AltGr => key_bonding.has_altgr = true,
+        }
+    }
+}
+
+/// A key or a modifier going down or up.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum KeyEvent {
+    Press(KeyboardKey),
+    Release(KeyboardKey),
+    ModifierPress(Modifier),
+    ModifierRelease(Modifier),
+}
+
+impl KeyEvent {
+    /// The event of the Linux key code `code`, `None` if the key has no `KeyboardKey` or `Modifier`.
+    pub fn from_code(code: u16, pressed: bool) -> Option<KeyEvent> {
+        if let Some(modifier) = Modifier::from_code(code) {
+            return Some(if pressed {
+                KeyEvent::ModifierPress(modifier)
+            } else {
+                KeyEvent::ModifierRelease(modifier)
+            });
+        }
+        KeyboardKey::from_code(code).map(|key| {
+            if pressed {
+                KeyEvent::Press(key)
+            } else {
+                KeyEvent::Release(key)
+            }
+        })
+    }
+    /// The event of the key named `name`, as `KeyboardKey::KeyA` is named `KeyA`.
+    pub fn from_name(name: &str, pressed: bool) -> Option<KeyEvent> {
+        if let Some(modifier) = MODIFIERS
+            .iter()
+            .find(|modifier| format!("{:?}", modifier) == name)
+        {
+            return KeyEvent::from_code(*modifier as u16, pressed);
+        }
+        (0..256)
+            .filter_map(KeyboardKey::from_code)
+            .find(|key| format!("{:?}", key) == name)
+            .and_then(|key| KeyEvent::from_code(key as u16, pressed))
+    }
+    /// The Linux key code of the key or the modifier.
+    pub fn code(&self) -> u16 {
+        match *self {
+            KeyEvent::Press(key) | KeyEvent::Release(key) => key as u16,
+            KeyEvent::ModifierPress(modifier) | KeyEvent::ModifierRelease(modifier) => {
+                modifier as u16
+            }
+        }
+    }
+    pub fn is_press(&self) -> bool {
+        match *self {
+            KeyEvent::Press(_) | KeyEvent::ModifierPress(_) => true,
+            KeyEvent::Release(_) | KeyEvent::ModifierRelease(_) => false,
+        }
+    }
+    /// The name of the key or the modifier.
+    pub fn key_name(&self) -> String {
+        match *self {
+            KeyEvent::Press(key) | KeyEvent::Release(key) => format!("{:?}", key),
+            KeyEvent::ModifierPress(modifier) | KeyEvent::ModifierRelease(modifier) => {
+                format!("{:?}", modifier)
+            }
+        }
+    }
+}
+
+/// A key event at `time` from the start of the timeline.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct TimedKeyEvent {
+    pub time: Duration,
+    pub event: KeyEvent,
+}
+
+/// A timeline of key events, in the order of their time.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Timeline {
+    pub events: Vec<TimedKeyEvent>,
+}
+
+#[cfg(feature = "json")]
+#[derive(Serialize, Deserialize)]
+struct JsonTimeline {
+    version: u32,
+    events: Vec<JsonEvent>,
+}
+
+#[cfg(feature = "json")]
+#[derive(Serialize, Deserialize)]
+struct JsonEvent {
+    time_ms: f64,
+    action: String,
+    key: String,
+}
+
+impl Timeline {
+    pub fn new(mut events: Vec<TimedKeyEvent>) -> Timeline {
+        events.sort_by_key(|event| event.time);
+        Timeline { events }
+    }
+    /// Write the timeline in the text format.
+    pub fn to_text(&self) -> String {
+        let mut text = format!("{} {}\n", TEXT_HEADER, FORMAT_VERSION);
+        for event in &self.events {
+            text.push_str(&format!(
+                "{} {} {}\n",
+                format_ms(event.time),
+                action_name(&event.event),
+                event.event.key_name()
+            ));
+        }
+        text
+    }
+    /// Read a timeline in the text format.
+    pub fn from_text(text: &str) -> Result<Timeline, String> {
+        let mut lines = text
+            .lines()
+            .enumerate()
+            .map(|(index, line)| (index + 1, line.trim()))
+            .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'));
+        match lines.next() {
+            Some((_, header)) if header.starts_with(TEXT_HEADER) => {
+                check_version(header[TEXT_HEADER.len()..].trim().parse().ok())?
+            }
+            _ => return Err(format!("missing header \"{}\"", TEXT_HEADER)),
+        }
+        let mut events = vec![];
+        for (number, line) in lines {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            if fields.len() != 3 {
+                return Err(format!("line {}: expected \"time action key\"", number));
+            }
+            events.push(
+                parse_event(fields[0].parse().ok(), fields[1], fields[2])
+                    .map_err(|error| format!("line {}: {}", number, error))?,
+            );
+        }
+        Ok(Timeline::new(events))
+    }
+    /// Write the timeline in the JSON format.
+    #[cfg(feature = "json")]
+    pub fn to_json(&self) -> Result<String, String> {
+        let json = JsonTimeline {
+            version: FORMAT_VERSION,
+            events: self
+                .events
+                .iter()
+                .map(|event| JsonEvent {
+                    time_ms: event.time.as_secs_f64() * 1000.0,
+                    action: action_name(&event.event).to_string(),
+                    key: event.event.key_name(),
+                })
+                .collect(),
+        };
+        serde_json::to_string(&json).map_err(|error| error.to_string())
+    }
+    /// Read a timeline in the JSON format.
+    #[cfg(feature = "json")]
+    pub fn from_json(json: &str) -> Result<Timeline, String> {
+        let json: JsonTimeline = serde_json::from_str(json).map_err(|error| error.to_string())?;
+        check_version(Some(json.version))?;
+        let mut events = vec![];
+        for (index, event) in json.events.iter().enumerate() {
+            events.push(
+                parse_event(Some(event.time_ms), &event.action, &event.key)
+                    .map_err(|error| format!("event {}: {}", index, error))?,
+            );
+        }
+        Ok(Timeline::new(events))
+    }
+}
+
+fn check_version(version: Option<u32>) -> Result<(), String> {
+    match version {
+        Some(FORMAT_VERSION) => Ok(()),
+        Some(version) => Err(format!("unsupported timeline version {}", version)),
+        None => Err("invalid timeline version".to_string()),
+    }
+}
+
+fn action_name(event: &KeyEvent) -> &'static str {
+    if event.is_press() {
+        "press"
+    } else {
+        "release"
+    }
+}
+
+fn format_ms(time: Duration) -> String {
+    let micros = time.as_micros();
+    if micros.is_multiple_of(1000) {
+        format!("{}", micros / 1000)
+    } else {
+        format!("{}.{:03}", micros / 1000, micros % 1000)
+    }
+}
+
+fn parse_event(time_ms: Option<f64>, action: &str, key: &str) -> Result<TimedKeyEvent, String> {
+    let time = match time_ms {
+        Some(ms) if ms.is_finite() && ms >= 0.0 => {
+            Duration::from_micros((ms * 1000.0).round() as u64)
+        }
+        _ => return Err("invalid time".to_string()),
+    };
+    let pressed = match action {
+        "press" => true,
+        "release" => false,
+        _ => return Err(format!("unknown action {}", action)),
+    };
+    match KeyEvent::from_name(key, pressed) {
+        Some(event) => Ok(TimedKeyEvent { time, event }),
+        None => Err(format!("unknown key {}", key)),
+    }
+}
+
+/// Player of a `Timeline` through any `KBPlatform`.
+///
+/// The timeline is played as its `key_bondings`, each key press with the modifiers held at that time.
+#[derive(Clone, Debug)]
+pub struct Player {
+    speed: f64,
+    max_idle: Option<Duration>,
+    loops: Option<u32>,
+    start: Duration,
+    stop: Option<Duration>,
+}
+
+impl Default for Player {
+    fn default() -> Player {
+        Player {
+            speed: 1.0,
+            max_idle: None,
+            loops: Some(1),
+            start: Duration::from_secs(0),
+            stop: None,
+        }
+    }
+}
+
+impl Player {
+    pub fn new() -> Player {
+        Player::default()
+    }
+    /// Play `speed` times faster, 0.5 plays at half speed.
+    pub fn speed(mut self, speed: f64) -> Player {
+        self.speed = speed;
+        self
+    }
+    /// Shorten the pauses of the playback longer than `max_idle`.
+    pub fn max_idle(mut self, max_idle: Duration) -> Player {
+        self.max_idle = Some(max_idle);
+        self
+    }
+    /// Play the timeline `count` times.
+    pub fn loops(mut self, count: u32) -> Player {
+        self.loops = Some(count);
+        self
+    }
+    /// Play the timeline until an error.
+    pub fn loop_forever(mut self) -> Player {
+        self.loops = None;
+        self
+    }
+    /// Skip the events before `offset` of the timeline, the keys held at `offset` are kept.
+    pub fn start_at(mut self, offset: Duration) -> Player {
+        self.start = offset;
+        self
+    }
+    /// Skip the events from `offset` of the timeline.
+    pub fn stop_at(mut self, offset: Duration) -> Player {
+        self.stop = Some(offset);
+        self
+    }
+    /// The `KeyBonding`s of one pass, with their time from the start of the pass.
+    pub fn schedule(&self, timeline: &Timeline) -> Result<Vec<(Duration, KeyBonding)>, String> {
+        let key_bondings = key_bondings(&sorted_events(timeline))
+            .into_iter()
+            .filter(|(time, _)| self.plays(*time))
+            .collect();
+        self.timed(key_bondings)
+    }
+    /// The presses and releases of one pass, with their time from the start of the pass.
+    ///
+    /// The keys held at the start offset are pressed at the start of the pass.
+    pub fn transitions(&self, timeline: &Timeline) -> Result<Vec<(Duration, KeyEvent)>, String> {
+        let mut held: Vec<KeyEvent> = vec![];
+        let mut transitions = vec![];
+        for timed in sorted_events(timeline) {
+            if timed.time >= self.start {
+                if self.plays(timed.time) {
+                    transitions.push((timed.time, timed.event));
+                }
+            } else if !timed.event.is_press() {
+                held.retain(|press| press.code() != timed.event.code());
+            } else if !held.contains(&timed.event) {
+                held.push(timed.event);
+            }
+        }
+        let pressed = held.into_iter().map(|press| (self.start, press));
+        self.timed(pressed.chain(transitions).collect())
+    }
+    /// Whether the event at `time` of the timeline is between the offsets.
+    fn plays(&self, time: Duration) -> bool {
+        time >= self.start && self.stop.is_none_or(|stop| time < stop)
+    }
+    /// Scale the times of `entries`, between the offsets and in order, and shorten the idle gaps.
+    fn timed<T>(&self, entries: Vec<(Duration, T)>) -> Result<Vec<(Duration, T)>, String> {
+        if !(self.speed.is_finite() && self.speed > 0.0) {
+            return Err(format!("invalid playback speed {}", self.speed));
+        }
+        let mut last = Duration::from_secs(0);
+        let mut elapsed = Duration::from_secs(0);
+        let mut schedule = vec![];
+        for (time, entry) in entries {
+            let time = Duration::try_from_secs_f64((time - self.start).as_secs_f64() / self.speed)
+                .map_err(|_| format!("invalid playback speed {}", self.speed))?;
+            let mut idle = time.saturating_sub(last);
+            if let Some(max_idle) = self.max_idle {
+                idle = idle.min(max_idle);
+            }
+            last = time;
+            elapsed += idle;
+            schedule.push((elapsed, entry));
+        }
+        Ok(schedule)
+    }
+    /// What `platform` plays in one pass: its presses and releases if it has key transitions,
+    /// else the `KeyBonding`s of the key presses.
+    fn playback(
+        &self,
+        timeline: &Timeline,
+        platform: &dyn KBPlatform,
+    ) -> Result<Vec<(Duration, Playback)>, String> {
+        if !platform.has_key_transitions() {
+            return Ok(self
+                .schedule(timeline)?
+                .into_iter()
+                .map(|(time, key_bonding)| (time, Playback::Launch(key_bonding)))
+                .collect());
+        }
+        let mut held: Vec<Modifier> = vec![];
+        let mut playback = vec![];
+        for (time, event) in self.transitions(timeline)? {
+            let keys = match event {
+                KeyEvent::Press(key) => vec![key],
+                KeyEvent::ModifierPress(modifier) => {
+                    if !held.contains(&modifier) {
+                        held.push(modifier);
+                    }
+                    vec![]
+                }
+                KeyEvent::ModifierRelease(modifier) => {
+                    held.retain(|held| *held != modifier);
+                    playback.push((time, Playback::Release(event.code())));
+                    continue;
+                }
+                KeyEvent::Release(_) => {
+                    playback.push((time, Playback::Release(event.code())));
+                    continue;
+                }
+            };
+            let key_bonding = held_key_bonding(keys, &held);
+            playback.push((time, Playback::Press(event.code(), key_bonding)));
+        }
+        Ok(playback)
+    }
+    /// Play `timeline` through `platform`, blocking until the end of the last pass.
+    ///
+    /// Fails with `emergency::STOPPED` if the emergency stop is pressed during the playback.
+    pub fn play(&self, timeline: &Timeline, platform: &mut dyn KBPlatform) -> Result<(), String> {
+        self.play_with(timeline, platform, &CancelToken::new(), &mut ())
+            .into_result()
+    }
+    /// Play `timeline` until `cancel` is cancelled, reporting each key press to `observer`.
+    ///
+    /// A platform with key transitions gets each press and release at its own time, the keys
+    /// still held at the end of a pass or of the playback are released. Other platforms get a
+    /// `KeyBonding` tapping each pressed key with the modifiers held at that time.
+    ///
+    /// A timeline without key press between the offsets finishes at once, even looping forever.
+    pub fn play_with(
+        &self,
+        timeline: &Timeline,
+        platform: &mut dyn KBPlatform,
+        cancel: &CancelToken,
+        observer: &mut dyn Observer,
+    ) -> Completion {
+        let playback = match self.playback(timeline, platform) {
+            Ok(playback) => playback,
+            Err(error) => {
+                return Run::new(cancel, observer, Some(0)).finish(Err(Outcome::Failed(error)))
+            }
+        };
+        let presses = playback
+            .iter()
+            .filter(|(_, entry)| !matches!(entry, Playback::Release(_)))
+            .count();
+        let steps_total = self.loops.map(|loops| presses * loops as usize);
+        let pass_duration = playback
+            .last()
+            .map_or(Duration::from_secs(0), |last| last.0);
+        let mut run = Run::new(cancel, observer, steps_total);
+        if presses == 0 {
+            return run.finish(Ok(()));
+        }
+        let mut pass = 0;
+        let mut result = Ok(());
+        let mut held: Vec<u16> = vec![];
+        while result.is_ok() && self.loops.is_none_or(|loops| pass < loops) {
+            result = run.check();
+            if result.is_err() {
+                break;
+            }
+            let start = Instant::now();
+            if let Some(loops) = self.loops {
+                run.expect_end(start + pass_duration * (loops - pass));
+            }
+            for (time, entry) in &playback {
+                result = run
+                    .sleep((start + *time).saturating_duration_since(Instant::now()))
+                    .and_then(|_| match *entry {
+                        Playback::Launch(ref key_bonding) => run
+                            .step(key_bonding.clone(), |key_bonding| {
+                                platform.run_action(key_bonding)
+                            }),
+                        Playback::Press(code, ref key_bonding) => {
+                            run.step(key_bonding.clone(), |_| platform.key_transition(code, true))?;
+                            if !held.contains(&code) {
+                                held.push(code);
+                            }
+                            Ok(())
+                        }
+                        Playback::Release(code) => {
+                            held.retain(|held| *held != code);
+                            platform
+                                .key_transition(code, false)
+                                .map_err(Outcome::Failed)
+                        }
+                    });
+                if result.is_err() {
+                    break;
+                }
+            }
+            // No key stays held after the pass, even when it failed or was stopped.
+            for code in held.drain(..).rev() {
+                let released = platform
+                    .key_transition(code, false)
+                    .map_err(Outcome::Failed);
+                if result.is_ok() {
+                    result = released;
+                }
+            }
+            pass += 1;
+        }
+        run.finish(result)
+    }
+}
+
+/// An entry of the playback of a pass.
+enum Playback {
+    /// Launch the `KeyBonding` with `KBPlatform::run_action`.
+    Launch(KeyBonding),
+    /// Press the key code, reported as the `KeyBonding` of the key with the held modifiers.
+    Press(u16, KeyBonding),
+    Release(u16),
+}
+
+/// The `KeyBonding`s playing back `events` with `KBPlatform::run_action`, one for each key press
+/// with the modifiers held at that time.
+pub fn key_bondings(events: &[TimedKeyEvent]) -> Vec<(Duration, KeyBonding)> {
+    let mut held: Vec<Modifier> = vec![];
+    let mut key_bondings = vec![];
+    for timed in events {
+        match timed.event {
+            KeyEvent::ModifierPress(modifier) => {
+                if !held.contains(&modifier) {
+                    held.push(modifier);
+                }
+            }
+            KeyEvent::ModifierRelease(modifier) => held.retain(|held| *held != modifier),
+            KeyEvent::Press(key) => {
+                key_bondings.push((timed.time, held_key_bonding(vec![key], &held)));
+            }
+            KeyEvent::Release(_) => {}
+        }
+    }
+    key_bondings
+}
+
+/// The `KeyBonding` of `keys` with the `held` modifiers.
+fn held_key_bonding(keys: Vec<KeyboardKey>, held: &[Modifier]) -> KeyBonding {
+    let mut key_bonding = KeyBonding {
+        keys,
+        ..KeyBonding::default()
+    };
+    for modifier in held {
+        modifier.apply(&mut key_bonding);
+    }
+    key_bonding
+}
+
+/// The events of `timeline` in the order of their time, its `events` may have been changed.
+fn sorted_events(timeline: &Timeline) -> Vec<TimedKeyEvent> {
+    let mut events = timeline.events.clone();
+    events.sort_by_key(|event| event.time);
+    events
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn key_presses_become_key_bondings_with_held_modifiers() {
+        let at = |ms, code, pressed| TimedKeyEvent {
+            time: Duration::from_millis(ms),
+            event: KeyEvent::from_code(code, pressed).unwrap(),
+        };
+        let events = [
+            at(0, 42, true),
+            at(10, 30, true),
+            at(20, 30, false),
+            at(30, 42, false),
+            at(40, 48, true),
+            at(50, 48, false),
+        ];
+        assert_eq!(events[0].event, KeyEvent::ModifierPress(Modifier::Shift));
+        let key_bondings = key_bondings(&events);
+        assert_eq!(key_bondings.len(), 2);
+        assert_eq!(key_bondings[0].0, Duration::from_millis(10));
+        assert!(key_bondings[0].1.has_shift);
+        assert_eq!(key_bondings[0].1.keys, vec![KeyboardKey::KeyA]);
+        assert!(!key_bondings[1].1.has_shift);
+        assert_eq!(key_bondings[1].1.keys, vec![KeyboardKey::KeyB]);
+    }
+
+    #[test]
+    fn text_format_round_trips() {
+        let text = "# recorded\nkeybd_event timeline 1\n\n0 press Shift\n12.5 press KeyA\n40 release KeyA\n52 release Shift\n";
+        let timeline = Timeline::from_text(text).unwrap();
+        assert_eq!(timeline.events.len(), 4);
+        assert_eq!(timeline.events[1].time, Duration::from_micros(12_500));
+        assert_eq!(timeline.events[1].event, KeyEvent::Press(KeyboardKey::KeyA));
+        assert_eq!(Timeline::from_text(&timeline.to_text()).unwrap(), timeline);
+        assert!(Timeline::from_text("keybd_event timeline 2\n").is_err());
+        assert!(Timeline::from_text("keybd_event timeline 1\n0 press KeyNope\n").is_err());
+        #[cfg(feature = "json")]
+        assert_eq!(
+            Timeline::from_json(&timeline.to_json().unwrap()).unwrap(),
+            timeline
+        );
+    }
+
+    struct Recording(Vec<KeyBonding>);
+
+    impl KBPlatform for Recording {
+        fn run_action(&mut self, key_bonding: KeyBonding) -> Result<(), String> {
+            self.0.push(key_bonding);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn player_scales_skips_and_loops() {
+        let at = |ms, code, pressed| TimedKeyEvent {
+            time: Duration::from_millis(ms),
+            event: KeyEvent::from_code(code, pressed).unwrap(),
+        };
+        let timeline = Timeline::new(vec![
+            at(0, 29, true),
+            at(100, 30, true),
+            at(200, 48, true),
+            at(5000, 46, true),
+            at(6000, 32, true),
+        ]);
+        let player = Player::new()
+            .speed(2.0)
+            .max_idle(Duration::from_millis(300))
+            .start_at(Duration::from_millis(100))
+            .stop_at(Duration::from_millis(6000));
+        let schedule = player.schedule(&timeline).unwrap();
+        let times: Vec<Duration> = schedule.iter().map(|(time, _)| *time).collect();
+        assert_eq!(
+            times,
+            vec![
+                Duration::from_millis(0),
+                Duration::from_millis(50),
+                Duration::from_millis(350)
+            ]
+        );
+        assert!(schedule.iter().all(|(_, key_bonding)| key_bonding.has_ctrl));
+        assert!(Player::new().speed(0.0).schedule(&timeline).is_err());
+        assert!(Player::new().speed(1e-30).schedule(&timeline).is_err());
+        let unsorted = Timeline {
+            events: timeline.events.iter().rev().cloned().collect(),
+        };
+        assert_eq!(player.schedule(&unsorted).unwrap(), schedule);
+
+        let mut recording = Recording(vec![]);
+        Player::new()
+            .speed(1000.0)
+            .loops(2)
+            .play(&timeline, &mut recording)
+            .unwrap();
+        assert_eq!(recording.0.len(), 8);
+        // Nothing to play between the offsets, looping forever ends at once.
+        Player::new()
+            .loop_forever()
+            .start_at(Duration::from_secs(7))
+            .play(&timeline, &mut recording)
+            .unwrap();
+        assert_eq!(recording.0.len(), 8);
+    }
+
+    struct Transitions(Vec<(Instant, u16, bool)>);
+
+    impl KBPlatform for Transitions {
+        fn run_action(&mut self, _key_bonding: KeyBonding) -> Result<(), String> {
+            Err("only transitions are played".to_string())
+        }
+        fn has_key_transitions(&self) -> bool {
+            true
+        }
+        fn key_transition(&mut self, code: u16, pressed: bool) -> Result<(), String> {
+            self.0.push((Instant::now(), code, pressed));
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn player_keeps_recorded_holds() {
+        let at = |ms, code, pressed| TimedKeyEvent {
+            time: Duration::from_millis(ms),
+            event: KeyEvent::from_code(code, pressed).unwrap(),
+        };
+        let timeline = Timeline::new(vec![
+            at(0, 42, true),
+            at(10, 30, true),
+            at(110, 30, false),
+            at(120, 42, false),
+            at(200, 48, true),
+            at(300, 46, true),
+        ]);
+        let mut transitions = Transitions(vec![]);
+        let started = Instant::now();
+        Player::new()
+            .stop_at(Duration::from_millis(250))
+            .play(&timeline, &mut transitions)
+            .unwrap();
+        let keys: Vec<(u16, bool)> = transitions
+            .0
+            .iter()
+            .map(|(_, code, pressed)| (*code, *pressed))
+            .collect();
+        // The key still held at the end of the pass is released.
+        assert_eq!(
+            keys,
+            vec![
+                (42, true),
+                (30, true),
+                (30, false),
+                (42, false),
+                (48, true),
+                (48, false)
+            ]
+        );
+        // KeyA is released at its recorded time, not right after its press.
+        assert!(transitions.0[2].0 - started >= Duration::from_millis(110));
+        // The keys held at the start offset are pressed at the start of the pass.
+        let held = Player::new()
+            .start_at(Duration::from_millis(50))
+            .stop_at(Duration::from_millis(115))
+            .transitions(&timeline)
+            .unwrap();
+        assert_eq!(
+            held,
+            vec![
+                (
+                    Duration::from_millis(0),
+                    KeyEvent::ModifierPress(Modifier::Shift)
+                ),
+                (Duration::from_millis(0), KeyEvent::Press(KeyboardKey::KeyA)),
+                (
+                    Duration::from_millis(60),
+                    KeyEvent::Release(KeyboardKey::KeyA)
+                )
+            ]
+        );
+    }
+}
@@ -0,0 +1,342 @@
+//! Virtual multitouch touchscreen on Linux, with the multitouch protocol B.
+use std::thread::sleep;
+use std::time::Duration;
+
+use linux::{AbsInfo, UInputBuilder, UInputDevice, DEVICE_NAME, EV_ABS, EV_KEY, INPUT_PROP_DIRECT};
+
+pub const ABS_X: u16 = 0x00;
+pub const ABS_Y: u16 = 0x01;
+pub const ABS_MT_SLOT: u16 = 0x2f;
+pub const ABS_MT_POSITION_X: u16 = 0x35;
+pub const ABS_MT_POSITION_Y: u16 = 0x36;
+pub const ABS_MT_TRACKING_ID: u16 = 0x39;
+
+pub const BTN_TOUCH: u16 = 0x14a;
+pub const BTN_TOOL_FINGER: u16 = 0x145;
+pub const BTN_TOOL_DOUBLETAP: u16 = 0x14d;
+pub const BTN_TOOL_TRIPLETAP: u16 = 0x14e;
+
+/// Options of the Linux virtual touchscreen.
+#[derive(Copy, Clone, Debug)]
+pub struct TouchConfig {
+    pub width: i32,
+    pub height: i32,
+    /// Units by millimeter of the axes.
+    pub resolution: i32,
+    /// Number of contacts tracked at once.
+    pub slots: i32,
+    /// Time a finger stays down for a tap.
+    pub tap: Duration,
+    /// Time a finger stays down for a long press.
+    pub long_press: Duration,
+    /// Duration of swipes and pinches.
+    pub gesture: Duration,
+    /// Time between two frames of swipes and pinches.
+    pub frame_interval: Duration,
+}
+
+impl Default for TouchConfig {
+    fn default() -> TouchConfig {
+        TouchConfig {
+            width: 1920,
+            height: 1080,
+            resolution: 10,
+            slots: 10,
+            tap: Duration::from_millis(50),
+            long_press: Duration::from_millis(800),
+            gesture: Duration::from_millis(300),
+            frame_interval: Duration::from_millis(10),
+        }
+    }
+}
+
+pub struct LinuxTouchscreen {
+    instance: UInputDevice,
+    config: TouchConfig,
+    /// Position of the contact of each slot, `None` when the slot is free.
+    contacts: Vec<Option<(i32, i32)>>,
+    slot: i32,
+    next_tracking_id: i32,
+}
+
+impl LinuxTouchscreen {
+    /// Create a 1920x1080 touchscreen.
+    pub fn new() -> Result<LinuxTouchscreen, String> {
+        LinuxTouchscreen::new_with_config(TouchConfig::default())
+    }
+    /// Create a touchscreen with the given options.
+    pub fn new_with_config(config: TouchConfig) -> Result<LinuxTouchscreen, String> {
+        if config.width <= 0 || config.height <= 0 {
+            return Err(format!(
+                "screen {}x{} has no position",
+                config.width, config.height
+            ));
+        }
+        if config.slots <= 0 {
+            return Err(format!("{} slots track no contact", config.slots));
+        }
+        let x = AbsInfo {
+            maximum: config.width - 1,
+            resolution: config.resolution,
+            ..AbsInfo::default()
+        };
+        let y = AbsInfo {
+            maximum: config.height - 1,
+            resolution: config.resolution,
+            ..AbsInfo::default()
+        };
+        let builder = UInputBuilder::new(&format!("{} touchscreen", DEVICE_NAME))
+            .property(INPUT_PROP_DIRECT)
+            .key(BTN_TOUCH)
+            .key(BTN_TOOL_FINGER)
+            .key(BTN_TOOL_DOUBLETAP)
+            .key(BTN_TOOL_TRIPLETAP)
+            .abs(ABS_X, x)
+            .abs(ABS_Y, y)
+            .abs(
+                ABS_MT_SLOT,
+                AbsInfo {
+                    maximum: config.slots - 1,
+                    ..AbsInfo::default()
+                },
+            )
+            .abs(
+                ABS_MT_TRACKING_ID,
+                AbsInfo {
+                    maximum: 0xffff,
+                    ..AbsInfo::default()
+                },
+            )
+            .abs(ABS_MT_POSITION_X, x)
+            .abs(ABS_MT_POSITION_Y, y);
+        Ok(LinuxTouchscreen::with_device(builder.create()?, config))
+    }
+    fn with_device(instance: UInputDevice, config: TouchConfig) -> LinuxTouchscreen {
+        LinuxTouchscreen {
+            instance,
+            config,
+            contacts: vec![None; config.slots as usize],
+            slot: 0,
+            next_tracking_id: 0,
+        }
+    }
+    /// Put a finger down in `slot`.
+    pub fn touch_down(&mut self, slot: usize, x: i32, y: i32) -> Result<(), String> {
+        self.write_down(slot, x, y)?;
+        self.frame()
+    }
+    /// Move the finger of `slot`.
+    pub fn touch_move(&mut self, slot: usize, x: i32, y: i32) -> Result<(), String> {
+        self.write_move(slot, x, y)?;
+        self.frame()
+    }
+    /// Lift the finger of `slot`.
+    pub fn touch_up(&mut self, slot: usize) -> Result<(), String> {
+        self.write_up(slot)?;
+        self.frame()
+    }
+    pub fn tap(&mut self, x: i32, y: i32) -> Result<(), String> {
+        let duration = self.config.tap;
+        self.press(x, y, duration)
+    }
+    pub fn long_press(&mut self, x: i32, y: i32) -> Result<(), String> {
+        let duration = self.config.long_press;
+        self.press(x, y, duration)
+    }
+    /// Slide a finger from `from` to `to`.
+    pub fn swipe(&mut self, from: (i32, i32), to: (i32, i32)) -> Result<(), String> {
+        self.write_down(0, from.0, from.1)?;
+        self.frame()?;
+        for ratio in self.gesture_steps() {
+            sleep(self.config.frame_interval);
+            self.write_move(0, lerp(from.0, to.0, ratio), lerp(from.1, to.1, ratio))?;
+            self.frame()?;
+        }
+        self.touch_up(0)
+    }
+    /// Move two fingers around `center` from `from` to `to` pixels apart, horizontally.
+    ///
+    /// A `to` distance smaller than `from` pinches in, larger pinches out.
+    pub fn pinch(&mut self, center: (i32, i32), from: i32, to: i32) -> Result<(), String> {
+        let (x, y) = center;
+        self.write_down(0, x - from / 2, y)?;
+        self.write_down(1, x + from / 2, y)?;
+        self.frame()?;
+        for ratio in self.gesture_steps() {
+            sleep(self.config.frame_interval);
+            let half = lerp(from, to, ratio) / 2;
+            self.write_move(0, x - half, y)?;
+            self.write_move(1, x + half, y)?;
+            self.frame()?;
+        }
+        self.write_up(0)?;
+        self.write_up(1)?;
+        self.frame()
+    }
+    fn press(&mut self, x: i32, y: i32, duration: Duration) -> Result<(), String> {
+        self.touch_down(0, x, y)?;
+        sleep(duration);
+        self.touch_up(0)
+    }
+    /// Ratios of the frames of a gesture, the last one is 1.
+    fn gesture_steps(&self) -> Vec<f64> {
+        let interval = self.config.frame_interval.as_micros().max(1);
+        let steps = (self.config.gesture.as_micros() / interval).max(1) as u32;
+        (1..steps + 1)
+            .map(|step| step as f64 / steps as f64)
+            .collect()
+    }
+    fn write_down(&mut self, slot: usize, x: i32, y: i32) -> Result<(), String> {
+        self.check_position(slot, x, y)?;
+        if self.contacts[slot].is_some() {
+            return Err(format!("slot {} is already touching", slot));
+        }
+        let tracking_id = self.next_tracking_id;
+        self.next_tracking_id = (self.next_tracking_id + 1) & 0xffff;
+        self.select_slot(slot)?;
+        self.instance
+            .write_event(EV_ABS, ABS_MT_TRACKING_ID, tracking_id)?;
+        self.instance.write_event(EV_ABS, ABS_MT_POSITION_X, x)?;
+        self.instance.write_event(EV_ABS, ABS_MT_POSITION_Y, y)?;
+        self.contacts[slot] = Some((x, y));
+        Ok(())
+    }
+    fn write_move(&mut self, slot: usize, x: i32, y: i32) -> Result<(), String> {
+        self.check_position(slot, x, y)?;
+        let (old_x, old_y) = match self.contacts[slot] {
+            Some(position) => position,
+            None => return Err(format!("slot {} is not touching", slot)),
+        };
+        self.select_slot(slot)?;
+        if x != old_x {
+            self.instance.write_event(EV_ABS, ABS_MT_POSITION_X, x)?;
+        }
+        if y != old_y {
+            self.instance.write_event(EV_ABS, ABS_MT_POSITION_Y, y)?;
+        }
+        self.contacts[slot] = Some((x, y));
+        Ok(())
+    }
+    fn write_up(&mut self, slot: usize) -> Result<(), String> {
+        if slot >= self.contacts.len() || self.contacts[slot].is_none() {
+            return Err(format!("slot {} is not touching", slot));
+        }
+        self.select_slot(slot)?;
+        self.instance.write_event(EV_ABS, ABS_MT_TRACKING_ID, -1)?;
+        self.contacts[slot] = None;
+        Ok(())
+    }
+    fn select_slot(&mut self, slot: usize) -> Result<(), String> {
+        if self.slot != slot as i32 {
+            self.slot = slot as i32;
+            self.instance.write_event(EV_ABS, ABS_MT_SLOT, self.slot)?;
+        }
+        Ok(())
+    }
+    fn check_position(&self, slot: usize, x: i32, y: i32) -> Result<(), String> {
+        if slot >= self.contacts.len() {
+            return Err(format!(
+                "slot {} is out of the {} slots",
+                slot,
+                self.contacts.len()
+            ));
+        }
+        if x < 0 || y < 0 || x >= self.config.width || y >= self.config.height {
+            return Err(format!("position {}x{} is out of the screen", x, y));
+        }
+        Ok(())
+    }
+    /// End the frame with the single-touch emulation of the first contact.
+    fn frame(&mut self) -> Result<(), String> {
+        let touching: Vec<(i32, i32)> = self.contacts.iter().filter_map(|c| *c).collect();
+        let count = touching.len();
+        self.instance
+            .write_event(EV_KEY, BTN_TOUCH, (count > 0) as i32)?;
+        self.instance
+            .write_event(EV_KEY, BTN_TOOL_FINGER, (count == 1) as i32)?;
+        self.instance
+            .write_event(EV_KEY, BTN_TOOL_DOUBLETAP, (count == 2) as i32)?;
+        self.instance
+            .write_event(EV_KEY, BTN_TOOL_TRIPLETAP, (count >= 3) as i32)?;
+        if let Some(&(x, y)) = touching.first() {
+            self.instance.write_event(EV_ABS, ABS_X, x)?;
+            self.instance.write_event(EV_ABS, ABS_Y, y)?;
+        }
+        self.instance.synchronize()?;
+        self.instance.flush()
+    }
+}
+
+fn lerp(from: i32, to: i32, ratio: f64) -> i32 {
+    from + ((to - from) as f64 * ratio).round() as i32
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+    use std::time::Duration;
+
+    use super::*;
+    use linux::tests::{read_events, stand_in};
+    use linux::{EV_SYN, SYN_REPORT};
+
+    #[test]
+    fn pinch_tracks_two_slots() {
+        let path = stand_in("touch");
+        {
+            let file = File::create(&path).unwrap();
+            let config = TouchConfig {
+                gesture: Duration::from_millis(1),
+                frame_interval: Duration::from_millis(1),
+                ..TouchConfig::default()
+            };
+            let mut touch = LinuxTouchscreen::with_device(UInputDevice::from_file(file), config);
+            touch.pinch((500, 500), 200, 100).unwrap();
+        }
+        let frames: Vec<Vec<(u16, u16, i32)>> = read_events(&path)
+            .into_iter()
+            .map(|(kind, code, value, _, _)| (kind, code, value))
+            .filter(|event| event.0 == EV_SYN || (event.0 == EV_ABS && event.1 > ABS_Y))
+            .collect::<Vec<_>>()
+            .split(|event| *event == (EV_SYN, SYN_REPORT, 0))
+            .map(|frame| frame.to_vec())
+            .filter(|frame| !frame.is_empty())
+            .collect();
+        assert_eq!(
+            frames,
+            vec![
+                vec![
+                    (EV_ABS, ABS_MT_TRACKING_ID, 0),
+                    (EV_ABS, ABS_MT_POSITION_X, 400),
+                    (EV_ABS, ABS_MT_POSITION_Y, 500),
+                    (EV_ABS, ABS_MT_SLOT, 1),
+                    (EV_ABS, ABS_MT_TRACKING_ID, 1),
+                    (EV_ABS, ABS_MT_POSITION_X, 600),
+                    (EV_ABS, ABS_MT_POSITION_Y, 500),
+                ],
+                vec![
+                    (EV_ABS, ABS_MT_SLOT, 0),
+                    (EV_ABS, ABS_MT_POSITION_X, 450),
+                    (EV_ABS, ABS_MT_SLOT, 1),
+                    (EV_ABS, ABS_MT_POSITION_X, 550),
+                ],
+                vec![
+                    (EV_ABS, ABS_MT_SLOT, 0),
+                    (EV_ABS, ABS_MT_TRACKING_ID, -1),
+                    (EV_ABS, ABS_MT_SLOT, 1),
+                    (EV_ABS, ABS_MT_TRACKING_ID, -1),
+                ],
+            ]
+        );
+        assert!(LinuxTouchscreen::new_with_config(TouchConfig {
+            slots: 0,
+            ..TouchConfig::default()
+        })
+        .is_err());
+        assert!(LinuxTouchscreen::new_with_config(TouchConfig {
+            height: 0,
+            ..TouchConfig::default()
+        })
+        .is_err());
+    }
+}
@@ -1,4 +1,4 @@
-use {KBPlatform, KeyBonding};
+use {KBPlatform, KeyBonding, KeyboardKey};
 
 #[link(name = "user32")]
 extern "C" {
@@ -20,7 +20,15 @@ const K_RCONTROL: u16 = 0xA3 + 0xFFF;
 pub struct WindowsKeyBD {}
 
 impl KBPlatform for WindowsKeyBD {
-    fn run_action(&mut self, key_bonding: KeyBonding) {
+    fn run_action(&mut self, key_bonding: KeyBonding) -> Result<(), String> {
+        // Nothing is sent for a KeyBonding with an unsupported key.
+        let mut keys = vec![];
+        for value in key_bonding.keys.iter() {
+            match WindowsKeyBD::convert_keycode(value) {
+                Some(key) => keys.push(key),
+                None => return Err(format!("{:?} is not supported on Windows", value)),
+            }
+        }
         unsafe {
             if key_bonding.has_alt {
                 self.down_key(K_ALT);
@@ -41,10 +49,10 @@ impl KBPlatform for WindowsKeyBD {
             if key_bonding.has_rctrl {
                 self.down_key(K_RCONTROL);
             }
-            key_bonding.keys.iter().for_each(|value| {
-                self.down_key(*value as u16);
-                self.up_key(*value as u16);
-            });
+            for key in keys {
+                self.down_key(key);
+                self.up_key(key);
+            }
             if key_bonding.has_alt {
                 self.up_key(K_ALT);
             }
@@ -65,13 +73,40 @@ impl KBPlatform for WindowsKeyBD {
                 self.up_key(K_RCONTROL);
             }
         }
+        Ok(())
     }
 }
 
 impl WindowsKeyBD {
-    pub fn new() -> Result<Box<KBPlatform>, String> {
+    pub fn new() -> Result<Box<dyn KBPlatform>, String> {
         Ok(Box::new(WindowsKeyBD {}))
     }
+    /// Media and system keys and Pause are virtual keys, the others are scan codes.
+    fn convert_keycode(keycode: &KeyboardKey) -> Option<u16> {
+        let virtual_key = match keycode {
+            KeyboardKey::KeyMute => 0xAD,
+            KeyboardKey::KeyVolumeDown => 0xAE,
+            KeyboardKey::KeyVolumeUp => 0xAF,
+            KeyboardKey::KeyNextSong => 0xB0,
+            KeyboardKey::KeyPreviousSong => 0xB1,
+            KeyboardKey::KeyStopCD => 0xB2,
+            KeyboardKey::KeyPlayPause => 0xB3,
+            KeyboardKey::KeyMail => 0xB4,
+            KeyboardKey::KeyCalc => 0xB7,
+            KeyboardKey::KeyBack => 0xA6,
+            KeyboardKey::KeyForward => 0xA7,
+            KeyboardKey::KeyRefresh => 0xA8,
+            KeyboardKey::KeySearch => 0xAA,
+            KeyboardKey::KeyBookmarks => 0xAB,
+            KeyboardKey::KeyHomePage => 0xAC,
+            KeyboardKey::KeySleep => 0x5F,
+            KeyboardKey::KeyPause => 0x13,
+            // No virtual key for these system keys.
+            KeyboardKey::KeyPower | KeyboardKey::KeyWakeUp => return None,
+            key => return Some(*key as u16),
+        };
+        Some(virtual_key + 0xFFF)
+    }
     unsafe fn down_key(&self, mut key: u16) {
         let mut flag = 0;
         if key < 0xFFF {
@@ -0,0 +1,243 @@
+//! A `KeyBondingInstance` owned by a background thread, shared between threads.
+//!
+//! The platforms are not `Send`, so the instance is created on the thread of the worker. The
+//! commands are run one after the other in the order they are queued, each one returns a
+//! `Pending` receiving its `Completion`.
+use std::sync::mpsc::{self, Receiver, Sender, SyncSender, TryRecvError, TrySendError};
+use std::thread;
+use std::time::Duration;
+
+use progress::{CancelToken, Completion, Outcome, Run};
+use {KeyBonding, KeyBondingInstance, KeyboardKey};
+
+/// Error of the commands sent to a worker whose thread ended.
+pub const WORKER_STOPPED: &str = "the keyboard worker stopped";
+
+/// What the worker does with its `KeyBondingInstance`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Command {
+    Launch(KeyBonding),
+    Sequence(Vec<KeyBonding>),
+    /// Text typed with a US keyboard layout.
+    Text(String),
+    Hold(KeyboardKey, Duration),
+}
+
+/// A queued command and the receiver of its completion.
+pub(crate) struct Job {
+    pub(crate) command: Command,
+    pub(crate) cancel: CancelToken,
+    pub(crate) done: Box<dyn FnOnce(Completion) + Send>,
+}
+
+/// Handle of a worker thread typing the commands queued from any thread.
+///
+/// The clones share the queue, the thread ends when the last clone is dropped, after the commands
+/// already queued.
+#[derive(Clone)]
+pub struct KeyboardWorker {
+    jobs: SyncSender<Job>,
+}
+
+impl KeyboardWorker {
+    /// Start a worker with `KeyBondingInstance::new`, queuing up to `capacity` commands.
+    pub fn new(capacity: usize) -> Result<KeyboardWorker, String> {
+        KeyboardWorker::with_instance(capacity, KeyBondingInstance::new)
+    }
+    /// Start a worker with the instance created by `instance` on the worker thread.
+    pub fn with_instance<F>(capacity: usize, instance: F) -> Result<KeyboardWorker, String>
+    where
+        F: FnOnce() -> Result<KeyBondingInstance, String> + Send + 'static,
+    {
+        let (jobs, queue) = mpsc::sync_channel::<Job>(capacity);
+        let (started, start) = mpsc::channel();
+        thread::Builder::new()
+            .name("keybd_event worker".to_string())
+            .spawn(move || {
+                let mut instance = match instance() {
+                    Ok(instance) => {
+                        started.send(Ok(())).ok();
+                        instance
+                    }
+                    Err(error) => {
+                        started.send(Err(error)).ok();
+                        return;
+                    }
+                };
+                for job in queue {
+                    let completion = run(&mut instance, job.command, &job.cancel);
+                    (job.done)(completion);
+                }
+            })
+            .map_err(|error| error.to_string())?;
+        match start.recv() {
+            Ok(Ok(())) => Ok(KeyboardWorker { jobs }),
+            Ok(Err(error)) => Err(error),
+            Err(_) => Err(WORKER_STOPPED.to_string()),
+        }
+    }
+    /// Queue `command`, waiting while the queue is full.
+    pub fn send(&self, command: Command) -> Result<Pending, String> {
+        let (job, pending) = job(command);
+        self.queue(job)?;
+        Ok(pending)
+    }
+    /// Queue `command`, fails if the queue is full.
+    pub fn try_send(&self, command: Command) -> Result<Pending, String> {
+        let (job, pending) = job(command);
+        match self.try_queue(job) {
+            Ok(()) => Ok(pending),
+            Err(TrySendError::Full(_)) => Err("the keyboard worker queue is full".to_string()),
+            Err(TrySendError::Disconnected(_)) => Err(WORKER_STOPPED.to_string()),
+        }
+    }
+    /// Queue `job`, waiting while the queue is full.
+    pub(crate) fn queue(&self, job: Job) -> Result<(), String> {
+        self.jobs.send(job).map_err(|_| WORKER_STOPPED.to_string())
+    }
+    pub(crate) fn try_queue(&self, job: Job) -> Result<(), TrySendError<Job>> {
+        self.jobs.try_send(job)
+    }
+    pub fn launch(&self, key_bonding: KeyBonding) -> Result<Pending, String> {
+        self.send(Command::Launch(key_bonding))
+    }
+    pub fn sequence(&self, key_bondings: Vec<KeyBonding>) -> Result<Pending, String> {
+        self.send(Command::Sequence(key_bondings))
+    }
+    pub fn text(&self, text: &str) -> Result<Pending, String> {
+        self.send(Command::Text(text.to_string()))
+    }
+}
+
+fn job(command: Command) -> (Job, Pending) {
+    let (done, receiver): (Sender<Completion>, _) = mpsc::channel();
+    let cancel = CancelToken::new();
+    let pending = Pending {
+        receiver,
+        cancel: cancel.clone(),
+    };
+    let done = Box::new(move |completion| {
+        done.send(completion).ok();
+    });
+    (
+        Job {
+            command,
+            cancel,
+            done,
+        },
+        pending,
+    )
+}
+
+fn run(instance: &mut KeyBondingInstance, command: Command, cancel: &CancelToken) -> Completion {
+    match command {
+        Command::Launch(key_bonding) => {
+            instance.launching_sequence_with(vec![key_bonding], cancel, &mut ())
+        }
+        Command::Sequence(key_bondings) => {
+            instance.launching_sequence_with(key_bondings, cancel, &mut ())
+        }
+        Command::Text(text) => instance.type_text_with(&text, cancel, &mut ()),
+        Command::Hold(key, duration) => {
+            let mut observer = ();
+            let mut run = Run::new(cancel, &mut observer, Some(1));
+            let key_bonding = KeyBonding {
+                keys: vec![key],
+                ..KeyBonding::default()
+            };
+            let result = run.step(key_bonding, |_| instance.hold_for(key, duration));
+            run.finish(result)
+        }
+    }
+}
+
+/// Completion of a queued command.
+pub struct Pending {
+    receiver: Receiver<Completion>,
+    cancel: CancelToken,
+}
+
+impl Pending {
+    /// Cancel the command, before it starts or between its steps.
+    pub fn cancel(&self) {
+        self.cancel.cancel();
+    }
+    /// Wait for the end of the command.
+    pub fn wait(self) -> Completion {
+        self.receiver
+            .recv()
+            .unwrap_or_else(|_| stopped_completion())
+    }
+    /// The completion if the command ended, without waiting.
+    pub fn try_wait(&self) -> Option<Completion> {
+        match self.receiver.try_recv() {
+            Ok(completion) => Some(completion),
+            Err(TryRecvError::Empty) => None,
+            Err(TryRecvError::Disconnected) => Some(stopped_completion()),
+        }
+    }
+}
+
+pub(crate) fn stopped_completion() -> Completion {
+    Completion {
+        progress: Default::default(),
+        outcome: Outcome::Failed(WORKER_STOPPED.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+    use KBPlatform;
+
+    struct Recording(Arc<Mutex<Vec<KeyBonding>>>);
+
+    impl KBPlatform for Recording {
+        fn run_action(&mut self, key_bonding: KeyBonding) -> Result<(), String> {
+            self.0.lock().unwrap().push(key_bonding);
+            Ok(())
+        }
+    }
+
+    fn shared<T: Clone + Send + Sync>(value: T) -> T {
+        value
+    }
+
+    #[test]
+    fn worker_runs_commands_in_order() {
+        let launched = Arc::new(Mutex::new(vec![]));
+        let platform = Recording(launched.clone());
+        let worker = shared(
+            KeyboardWorker::with_instance(2, move || {
+                KeyBondingInstance::new_with_platform(Box::new(platform))
+            })
+            .unwrap(),
+        );
+        let other = worker.clone();
+        let text = thread::spawn(move || other.text("hi").unwrap())
+            .join()
+            .unwrap();
+        let copy = KeyBonding {
+            has_ctrl: true,
+            keys: vec![KeyboardKey::KeyC],
+            ..KeyBonding::default()
+        };
+        let launch = worker.launch(copy.clone()).unwrap();
+        let hold = worker
+            .send(Command::Hold(KeyboardKey::KeyA, Duration::from_millis(1)))
+            .unwrap();
+        assert_eq!(text.wait().progress.characters_typed, 2);
+        assert!(launch.wait().is_finished());
+        // The recording platform can not hold a key.
+        match hold.wait().outcome {
+            Outcome::Failed(_) => {}
+            outcome => panic!("{:?}", outcome),
+        }
+        let launched = launched.lock().unwrap();
+        assert_eq!(launched.len(), 3);
+        assert_eq!(launched[2], copy);
+
+        assert!(KeyboardWorker::with_instance(1, || Err("no device".to_string())).is_err());
+    }
+}